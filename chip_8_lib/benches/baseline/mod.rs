@@ -0,0 +1,185 @@
+// The baseline for the instruction cache benchmark: how the interpreter worked before the
+// cache, matching on the raw opcode every cycle. It follows the Cpu's default quirks, so both
+// have to end up in the same state, but skips everything the Cpu does for frontends (key
+// events, dirty rectangles, written memory, profiling), so it's a fair bound on dispatch speed.
+use chip_8_lib::cpu::{CHIP8_FONT_SET, PROGRAM_START};
+use chip_8_lib::rng::Rng;
+
+const WIDTH: usize = 64;
+const HEIGHT: usize = 32;
+
+pub struct Baseline {
+    pub memory: [u8; 4096],
+    pub pc: u16,
+    pub v: [u8; 16],
+    pub i: u16,
+    pub stack: [u16; 16],
+    pub sp: u8,
+    pub display: [u8; WIDTH * HEIGHT],
+    pub dt: u8,
+    pub st: u8,
+    pub keys: [bool; 16],
+    pub rng: Rng,
+
+    // Keys held when Fx0A started waiting, they have to be let go before they count
+    held: Option<[bool; 16]>,
+}
+
+impl Baseline {
+    pub fn new(rom: &[u8], rng: Rng) -> Baseline {
+        let mut cpu = Baseline {
+            memory: [0; 4096],
+            pc: PROGRAM_START as u16,
+            v: [0; 16],
+            i: 0,
+            stack: [0; 16],
+            sp: 0,
+            display: [0; WIDTH * HEIGHT],
+            dt: 0,
+            st: 0,
+            keys: [false; 16],
+            rng,
+            held: None,
+        };
+        cpu.memory[..CHIP8_FONT_SET.len()].copy_from_slice(&CHIP8_FONT_SET);
+        let len = rom.len().min(cpu.memory.len() - PROGRAM_START);
+        cpu.memory[PROGRAM_START..PROGRAM_START + len].copy_from_slice(&rom[..len]);
+        cpu
+    }
+
+    // Like the Cpu, the program counter moves past an instruction that fails
+    pub fn emulate_cycle(&mut self) -> Result<(), ()> {
+        let result = self.execute();
+        self.pc = result.unwrap_or(self.pc + 2) & 0xFFF;
+        result.map(|_| ())
+    }
+
+    // Runs the instruction at the program counter, returning the address of the next one
+    fn execute(&mut self) -> Result<u16, ()> {
+        let pc = self.pc as usize;
+        let opcode = (self.memory[pc] as u16) << 8 | self.memory[(pc + 1) & 0xFFF] as u16;
+        let x = (opcode >> 8 & 0xF) as usize;
+        let y = (opcode >> 4 & 0xF) as usize;
+        let kk = opcode as u8;
+        let nnn = opcode & 0xFFF;
+        let mut next = self.pc + 2;
+
+        match opcode {
+            0x00E0 => self.display = [0; WIDTH * HEIGHT],
+            0x00EE => {
+                if self.sp == 0 {
+                    return Err(());
+                }
+                next = self.stack[self.sp as usize] + 2;
+                self.sp -= 1;
+            }
+            0x1000..=0x1FFF => next = nnn,
+            0x2000..=0x2FFF => {
+                if self.sp as usize + 1 >= self.stack.len() {
+                    return Err(());
+                }
+                self.sp += 1;
+                self.stack[self.sp as usize] = self.pc;
+                next = nnn;
+            }
+            0x3000..=0x3FFF => next += 2 * (self.v[x] == kk) as u16,
+            0x4000..=0x4FFF => next += 2 * (self.v[x] != kk) as u16,
+            0x5000..=0x5FFF if opcode & 0xF == 0 => next += 2 * (self.v[x] == self.v[y]) as u16,
+            0x6000..=0x6FFF => self.v[x] = kk,
+            0x7000..=0x7FFF => self.v[x] = self.v[x].wrapping_add(kk),
+            0x8000..=0x8FFF => match opcode & 0xF {
+                0x0 => self.v[x] = self.v[y],
+                0x1 => self.v[x] |= self.v[y],
+                0x2 => self.v[x] &= self.v[y],
+                0x3 => self.v[x] ^= self.v[y],
+                0x4 => {
+                    let (value, carry) = self.v[x].overflowing_add(self.v[y]);
+                    self.v[x] = value;
+                    self.v[0xF] = carry as u8;
+                }
+                0x5 => {
+                    let (value, borrow) = self.v[x].overflowing_sub(self.v[y]);
+                    self.v[x] = value;
+                    self.v[0xF] = !borrow as u8;
+                }
+                0x6 => {
+                    let value = self.v[x];
+                    self.v[x] = value >> 1;
+                    self.v[0xF] = value & 1;
+                }
+                0x7 => {
+                    let (value, borrow) = self.v[y].overflowing_sub(self.v[x]);
+                    self.v[x] = value;
+                    self.v[0xF] = !borrow as u8;
+                }
+                0xE => {
+                    let value = self.v[x];
+                    self.v[x] = value << 1;
+                    self.v[0xF] = value >> 7;
+                }
+                _ => return Err(()),
+            },
+            0x9000..=0x9FFF if opcode & 0xF == 0 => next += 2 * (self.v[x] != self.v[y]) as u16,
+            0xA000..=0xAFFF => self.i = nnn,
+            0xB000..=0xBFFF => next = self.v[0] as u16 + nnn,
+            0xC000..=0xCFFF => self.v[x] = self.rng.next_u8() & kk,
+            0xD000..=0xDFFF => {
+                let (vx, vy) = (self.v[x] as usize, self.v[y] as usize);
+                self.v[0xF] = 0;
+                for row in 0..(opcode & 0xF) as usize {
+                    let bits = self.memory[(self.i as usize + row) & 0xFFF];
+                    for col in 0..8 {
+                        if bits >> (7 - col) & 1 == 1 {
+                            let pixel = &mut self.display[(vx + col) % WIDTH + (vy + row) % HEIGHT * WIDTH];
+                            self.v[0xF] |= *pixel;
+                            *pixel ^= 1;
+                        }
+                    }
+                }
+            }
+            0xE000..=0xEFFF if kk == 0x9E => next += 2 * self.keys[(self.v[x] & 0xF) as usize] as u16,
+            0xE000..=0xEFFF if kk == 0xA1 => next += 2 * !self.keys[(self.v[x] & 0xF) as usize] as u16,
+            0xF000..=0xFFFF => match kk {
+                0x07 => self.v[x] = self.dt,
+                0x0A => {
+                    let keys = self.keys;
+                    let held = self.held.get_or_insert(keys);
+                    for (held, pressed) in held.iter_mut().zip(keys.iter()) {
+                        *held &= *pressed;
+                    }
+                    match (0..16).find(|key| keys[*key] && !held[*key]) {
+                        Some(key) => {
+                            self.v[x] = key as u8;
+                            self.held = None;
+                        }
+                        None => next = self.pc,
+                    }
+                }
+                0x15 => self.dt = self.v[x],
+                0x18 => self.st = self.v[x],
+                0x1E => self.i = self.i.wrapping_add(self.v[x] as u16),
+                0x29 => self.i = self.v[x] as u16 * 5,
+                0x33 => {
+                    let digits = [self.v[x] / 100, self.v[x] / 10 % 10, self.v[x] % 10];
+                    for (offset, digit) in digits.iter().enumerate() {
+                        self.memory[(self.i as usize + offset) & 0xFFF] = *digit;
+                    }
+                }
+                0x55 => {
+                    for offset in 0..=x {
+                        self.memory[(self.i as usize + offset) & 0xFFF] = self.v[offset];
+                    }
+                }
+                0x65 => {
+                    for offset in 0..=x {
+                        self.v[offset] = self.memory[(self.i as usize + offset) & 0xFFF];
+                    }
+                }
+                _ => return Err(()),
+            },
+            _ => return Err(()),
+        }
+
+        Ok(next)
+    }
+}
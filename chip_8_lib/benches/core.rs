@@ -0,0 +1,190 @@
+// Benchmarks for the hot paths of the Cpu. Run with `cargo bench --bench core`.
+//
+// Each benchmark is warmed up, then timed over a number of samples. The median time per
+// iteration is reported, along with the spread of the samples, and compared against the
+// saved baseline. The run fails if anything got slower than the baseline by more than the
+// threshold, so regressions are caught:
+//
+//     cargo bench --bench core -- --save-baseline   # on the commit to compare against
+//     cargo bench --bench core                       # after the change
+//     cargo bench --bench core -- --threshold 5     # fail on a 5% slowdown instead of 10%
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::hint::black_box;
+use std::path::Path;
+use std::process;
+use std::time::{Duration, Instant};
+
+use chip_8_lib::cpu::Cpu;
+use chip_8_lib::rng::Rng;
+
+const BASELINE_FILE: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/../target/bench-baselines/core.txt");
+
+const WARM_UP: Duration = Duration::from_millis(200);
+const SAMPLES: usize = 30;
+const SAMPLE_TIME: Duration = Duration::from_millis(50);
+
+// Percent slower than the baseline a benchmark can get before the run fails
+const DEFAULT_THRESHOLD: f64 = 10.0;
+
+// Instructions executed per iteration of the emulation benchmarks
+const CYCLES: usize = 10_000;
+
+// Median nanoseconds per iteration of each benchmark that has run
+#[derive(Default)]
+struct Results {
+    medians: BTreeMap<String, f64>,
+}
+
+impl Results {
+    fn bench<F: FnMut()>(&mut self, name: &str, elements: usize, mut f: F) {
+        let warm_up_start = Instant::now();
+        let mut iterations_per_sample: u64 = 0;
+        while warm_up_start.elapsed() < WARM_UP {
+            f();
+            iterations_per_sample += 1;
+        }
+        // Size the samples from the warm up so each takes about SAMPLE_TIME
+        iterations_per_sample = (iterations_per_sample * SAMPLE_TIME.as_nanos() as u64 / WARM_UP.as_nanos() as u64).max(1);
+
+        let mut samples: Vec<f64> = (0..SAMPLES)
+            .map(|_| {
+                let start = Instant::now();
+                for _ in 0..iterations_per_sample {
+                    f();
+                }
+                start.elapsed().as_nanos() as f64 / iterations_per_sample as f64
+            })
+            .collect();
+        samples.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        let median = samples[SAMPLES / 2];
+        let mut deviations: Vec<f64> = samples.iter().map(|sample| (sample - median).abs()).collect();
+        deviations.sort_by(|a, b| a.partial_cmp(b).unwrap());
+
+        println!(
+            "{:<12} {:>12.3} us/iter  +/- {:>5.1}%  {:>14.0} elements/s",
+            name,
+            median / 1000.0,
+            deviations[SAMPLES / 2] / median * 100.0,
+            elements as f64 * 1e9 / median
+        );
+        self.medians.insert(name.to_string(), median);
+    }
+
+    fn to_text(&self) -> String {
+        self.medians.iter().map(|(name, median)| format!("{} {:.1}\n", name, median)).collect()
+    }
+
+    fn parse(text: &str) -> Results {
+        let medians = text
+            .lines()
+            .filter_map(|line| {
+                let mut parts = line.split_whitespace();
+                Some((parts.next()?.to_string(), parts.next()?.parse().ok()?))
+            })
+            .collect();
+        Results { medians }
+    }
+
+    // The benchmarks more than `threshold` percent slower than in the baseline
+    fn regressions(&self, baseline: &Results, threshold: f64) -> Vec<String> {
+        let mut regressions = vec![];
+        for (name, median) in self.medians.iter() {
+            if let Some(before) = baseline.medians.get(name) {
+                let change = (median / before - 1.0) * 100.0;
+                println!("{:<12} {:>+7.1}% against the baseline", name, change);
+                if change > threshold {
+                    regressions.push(format!("{} is {:.1}% slower", name, change));
+                }
+            }
+        }
+        regressions
+    }
+}
+
+fn cpu_with(rom: Vec<u8>) -> Cpu {
+    let mut cpu = Cpu::new();
+    cpu.rng = Some(Rng::new(1));
+    cpu.load_game(rom);
+    cpu
+}
+
+fn run(cpu: &mut Cpu) {
+    for _ in 0..CYCLES {
+        cpu.emulate_cycle().unwrap();
+    }
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let save_baseline = args.iter().any(|arg| arg == "--save-baseline");
+    let threshold = match args.iter().position(|arg| arg == "--threshold") {
+        Some(idx) => args.get(idx + 1).and_then(|value| value.parse().ok()).unwrap_or_else(|| {
+            eprintln!("--threshold expects a percentage");
+            process::exit(1);
+        }),
+        None => DEFAULT_THRESHOLD,
+    };
+
+    let mut results = Results::default();
+
+    // A tight loop of register arithmetic, skips and jumps
+    let mut dispatch = cpu_with(vec![
+        0x60, 0x01, // 0x200 LD V0 1
+        0x80, 0x14, // 0x202 ADD V0 V1
+        0x81, 0x22, // 0x204 AND V1 V2
+        0x73, 0x01, // 0x206 ADD V3 1
+        0x33, 0x00, // 0x208 SE V3 0
+        0x12, 0x02, // 0x20A JP 0x202
+        0x12, 0x00, // 0x20C JP 0x200
+    ]);
+    results.bench("dispatch", CYCLES, || run(black_box(&mut dispatch)));
+
+    // Draws a 15 row sprite every other instruction, moving it so it wraps around the screen
+    let mut sprite = vec![
+        0xA2, 0x10, // 0x200 LD I 0x210
+        0xD0, 0x1F, // 0x202 DRW V0 V1 15
+        0x70, 0x07, // 0x204 ADD V0 7
+        0xD0, 0x1F, // 0x206 DRW V0 V1 15
+        0x71, 0x03, // 0x208 ADD V1 3
+        0x12, 0x02, // 0x20A JP 0x202
+        0x00, 0x00, 0x00, 0x00,
+    ];
+    sprite.extend_from_slice(&[0xA5; 15]);
+    let mut drawing = cpu_with(sprite);
+    results.bench("drw", CYCLES, || run(black_box(&mut drawing)));
+
+    // Save states of a machine part way into a game
+    let mut game = cpu_with(include_bytes!("../../chip_8_wasm/static/roms/BRIX").to_vec());
+    for _ in 0..CYCLES {
+        game.emulate_cycle().unwrap();
+    }
+    let state = game.save_state();
+    results.bench("save_state", 1, || {
+        black_box(game.save_state());
+    });
+    let mut restored = Cpu::new();
+    results.bench("load_state", 1, || {
+        restored.load_state(black_box(&state)).unwrap();
+    });
+
+    if save_baseline {
+        fs::create_dir_all(Path::new(BASELINE_FILE).parent().unwrap()).unwrap();
+        fs::write(BASELINE_FILE, results.to_text()).unwrap();
+        println!("Saved the baseline to {}", BASELINE_FILE);
+        return;
+    }
+    let baseline = match fs::read_to_string(BASELINE_FILE) {
+        Ok(text) => Results::parse(&text),
+        Err(_) => {
+            println!("No baseline to compare against, save one with `cargo bench --bench core -- --save-baseline`");
+            return;
+        }
+    };
+    let regressions = results.regressions(&baseline, threshold);
+    if !regressions.is_empty() {
+        eprintln!("Slower than the baseline by more than {}%: {}", threshold, regressions.join(", "));
+        process::exit(1);
+    }
+}
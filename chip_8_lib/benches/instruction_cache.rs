@@ -0,0 +1,140 @@
+// Compares the instruction cache against matching on the raw opcode every cycle, the way the
+// interpreter worked before the cache, and against decoding every instruction each cycle
+// without the cache. Run with `cargo bench --bench instruction_cache`.
+use std::time::{Duration, Instant};
+
+use chip_8_lib::cpu::{Cpu, CYCLES_PER_FRAME};
+use chip_8_lib::rng::Rng;
+
+#[path = "../tests/common/mod.rs"]
+mod common;
+
+mod baseline;
+
+use baseline::Baseline;
+use common::bundled_roms;
+
+const CYCLES: usize = 1_000_000;
+
+// Every ROM is run this many times by each engine, which keeps its fastest run, the one least
+// disturbed by whatever else the machine was doing
+const RUNS: usize = 5;
+
+// Presses a different key every so often, so games waiting on Fx0A get going and more of
+// each game is run. Frontends set the keys once a frame, so this does too.
+fn keys(cycle: usize) -> [bool; 16] {
+    let mut keys = [false; 16];
+    keys[(cycle / 500) % 16] = cycle % 1000 < 500;
+    keys
+}
+
+// The state compared between engines: pc, v, i, memory and display
+type State = (u16, [u8; 16], u16, Vec<u8>, Vec<u8>);
+
+// Runs a ROM until it errors or CYCLES have been executed, returning the cycles run, the
+// final state and the time taken
+fn run(rom: &[u8], use_instruction_cache: bool) -> (usize, State, Duration) {
+    let mut cpu = Cpu::new();
+    cpu.use_instruction_cache = use_instruction_cache;
+    cpu.rng = Some(Rng::new(1));
+    cpu.load_game(rom.to_vec());
+
+    let start = Instant::now();
+    let mut cycles = 0;
+    while cycles < CYCLES {
+        if cycles % CYCLES_PER_FRAME == 0 {
+            cpu.keys = keys(cycles);
+        }
+        cycles += 1;
+        if cpu.emulate_cycle().is_err() {
+            break;
+        }
+    }
+    let time = start.elapsed();
+    (cycles, (cpu.pc, cpu.v, cpu.i, cpu.memory.to_vec(), cpu.display.to_vec()), time)
+}
+
+// Runs the baseline the same way
+fn run_baseline(rom: &[u8]) -> (usize, State, Duration) {
+    let mut cpu = Baseline::new(rom, Rng::new(1));
+
+    let start = Instant::now();
+    let mut cycles = 0;
+    while cycles < CYCLES {
+        if cycles % CYCLES_PER_FRAME == 0 {
+            cpu.keys = keys(cycles);
+        }
+        cycles += 1;
+        if cpu.emulate_cycle().is_err() {
+            break;
+        }
+    }
+    let time = start.elapsed();
+    (cycles, (cpu.pc, cpu.v, cpu.i, cpu.memory.to_vec(), cpu.display.to_vec()), time)
+}
+
+// The fastest run of the baseline, the uncached and the cached engines. They take turns so
+// they all run under the same conditions, and every run of an engine has to end the same way.
+fn fastest(name: &str, rom: &[u8]) -> [(usize, State, Duration); 3] {
+    let engines: [&dyn Fn() -> (usize, State, Duration); 3] =
+        [&|| run_baseline(rom), &|| run(rom, false), &|| run(rom, true)];
+    let mut best = engines.map(|engine| engine());
+    for _ in 1..RUNS {
+        for (engine, best) in engines.iter().zip(best.iter_mut()) {
+            let run = engine();
+            assert!(run.0 == best.0 && run.1 == best.1, "{} doesn't run the same way twice", name);
+            best.2 = best.2.min(run.2);
+        }
+    }
+    best
+}
+
+// Nanoseconds per instruction
+fn ns(cycles: usize, time: Duration) -> f64 {
+    time.as_secs_f64() * 1e9 / cycles as f64
+}
+
+fn main() {
+    println!("Nanoseconds per instruction, fastest of {} runs", RUNS);
+    println!("{:<10} {:>10} {:>10} {:>10} {:>10}", "rom", "baseline", "uncached", "cached", "speedup");
+
+    let mut totals = [(0, Duration::default()); 3];
+    let mut slower = vec![];
+    for (name, rom) in bundled_roms() {
+        let [(cycles, baseline_state, baseline_time), (uncached_cycles, uncached_state, uncached_time), (cached_cycles, cached_state, cached_time)] =
+            fastest(&name, &rom);
+
+        // Every engine has to end up in exactly the same state for the comparison to mean anything
+        assert!(
+            cycles == uncached_cycles && baseline_state == uncached_state,
+            "{} ended in a different state matching on opcodes",
+            name
+        );
+        assert!(
+            cycles == cached_cycles && cached_state == uncached_state,
+            "{} ended in a different state with the instruction cache",
+            name
+        );
+
+        let runs = [(cycles, baseline_time), (cycles, uncached_time), (cycles, cached_time)];
+        for (total, (cycles, time)) in totals.iter_mut().zip(runs.iter()) {
+            total.0 += cycles;
+            total.1 += *time;
+        }
+        if cached_time > baseline_time {
+            slower.push(name.clone());
+        }
+        print_row(&name, &runs);
+    }
+    print_row("total", &totals);
+
+    if !slower.is_empty() {
+        println!("\nSlower with the instruction cache: {}", slower.join(", "));
+    }
+}
+
+// The speedup is the cached engine against matching on opcodes
+fn print_row(name: &str, runs: &[(usize, Duration); 3]) {
+    let [baseline, uncached, cached] = runs.map(|(cycles, time)| ns(cycles, time));
+    println!("{:<10} {:>10.2} {:>10.2} {:>10.2} {:>9.2}x", name, baseline, uncached, cached, baseline / cached);
+}
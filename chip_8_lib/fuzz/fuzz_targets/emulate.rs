@@ -0,0 +1,39 @@
+// Runs arbitrary bytes as a ROM. Run with `cargo +nightly fuzz run emulate` from chip_8_lib.
+//
+// The first byte picks the quirk profile and seeds Cxkk, the rest is the ROM. Any panic is a
+// bug, as is the program counter leaving memory or a save state not restoring exactly.
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+
+use chip_8_lib::cpu::Cpu;
+use chip_8_lib::quirks::Quirks;
+use chip_8_lib::rng::Rng;
+
+const CYCLES: usize = 10_000;
+
+fuzz_target!(|data: &[u8]| {
+    let (config, rom) = match data.split_first() {
+        Some((config, rom)) => (*config, rom),
+        None => return,
+    };
+    let (_, quirks) = Quirks::PROFILES[config as usize % Quirks::PROFILES.len()];
+
+    let mut cpu = Cpu::new();
+    cpu.quirks = quirks;
+    cpu.rng = Some(Rng::new(config as u64));
+    cpu.load_game(rom.to_vec());
+
+    for cycle in 0..CYCLES {
+        // Hold each key in turn so key dependent code runs too
+        cpu.keys = [false; 16];
+        cpu.keys[(cycle / 100) % 16] = true;
+
+        let _ = cpu.emulate_cycle();
+        assert!((cpu.pc as usize) < cpu.memory.len());
+    }
+
+    let state = cpu.save_state();
+    let mut restored = Cpu::new();
+    restored.load_state(&state).unwrap();
+    assert_eq!(restored.save_state(), state);
+});
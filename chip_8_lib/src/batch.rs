@@ -0,0 +1,206 @@
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
+
+use crate::checksum;
+use crate::cpu::{Cpu, CYCLES_PER_FRAME, PROGRAM_START};
+use crate::frame_result::FrameResult;
+use crate::json;
+use crate::quirks::Quirks;
+use crate::rng::Rng;
+
+// One ROM to run headlessly with one configuration
+#[derive(Clone)]
+pub struct BatchJob {
+    pub rom_name: String,
+    pub rom: Vec<u8>,
+    pub profile: String,
+    pub quirks: Quirks,
+}
+
+impl BatchJob {
+    // Every ROM with every configuration
+    pub fn cross(roms: &[(String, Vec<u8>)], profiles: &[(String, Quirks)]) -> Vec<BatchJob> {
+        let mut jobs = vec![];
+        for (rom_name, rom) in roms {
+            for (profile, quirks) in profiles {
+                jobs.push(BatchJob {
+                    rom_name: rom_name.clone(),
+                    rom: rom.clone(),
+                    profile: profile.clone(),
+                    quirks: *quirks,
+                });
+            }
+        }
+        jobs
+    }
+}
+
+pub struct BatchConfig {
+    // Frames to run each job for, unless it stops with an error first
+    pub frames: u64,
+
+    // Number of worker threads
+    pub threads: usize,
+
+    // Seed for Cxkk, every job uses the same one so results are reproducible
+    pub seed: u64,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct BatchResult {
+    pub rom_name: String,
+    pub profile: String,
+    pub frames: u64,
+    pub instructions: u64,
+
+    // CRC-32 of the display at the end of the run
+    pub display_hash: u32,
+
+    // The error that stopped the run early, if any
+    pub error: Option<String>,
+}
+
+pub fn run_job(job: &BatchJob, config: &BatchConfig) -> BatchResult {
+    let mut cpu = Cpu::new();
+    cpu.quirks = job.quirks;
+    cpu.rng = Some(Rng::new(config.seed));
+
+    let mut frames = 0;
+    let mut error = None;
+    if job.rom.len() > cpu.memory.len() - PROGRAM_START {
+        error = Some(format!("ROM is {} bytes, too big to fit in memory", job.rom.len()));
+    } else {
+        cpu.load_game(job.rom.clone());
+    }
+
+    // Like a frontend, a frame waiting on Fx0A ends there rather than running it again and again
+    while error.is_none() && frames < config.frames {
+        if let FrameResult::Error { message, .. } = cpu.run_frame_cycles(CYCLES_PER_FRAME) {
+            error = Some(message);
+            break;
+        }
+        frames += 1;
+    }
+
+    BatchResult {
+        rom_name: job.rom_name.clone(),
+        profile: job.profile.clone(),
+        frames,
+        instructions: cpu.cycle,
+        display_hash: checksum::crc32(&cpu.display),
+        error,
+    }
+}
+
+// Runs the jobs across config.threads threads. Results are in the same order as the jobs.
+pub fn run_batch(jobs: &[BatchJob], config: &BatchConfig) -> Vec<BatchResult> {
+    let next_job = AtomicUsize::new(0);
+    let results: Mutex<Vec<Option<BatchResult>>> = Mutex::new(vec![None; jobs.len()]);
+
+    thread::scope(|scope| {
+        for _ in 0..config.threads.max(1) {
+            scope.spawn(|| loop {
+                let idx = next_job.fetch_add(1, Ordering::SeqCst);
+                if idx >= jobs.len() {
+                    break;
+                }
+                // A ROM that crashes the emulator is reported like any other error
+                let job = &jobs[idx];
+                let result = panic::catch_unwind(AssertUnwindSafe(|| run_job(job, config))).unwrap_or_else(|_| {
+                    BatchResult {
+                        rom_name: job.rom_name.clone(),
+                        profile: job.profile.clone(),
+                        frames: 0,
+                        instructions: 0,
+                        display_hash: 0,
+                        error: Some(String::from("emulator panicked")),
+                    }
+                });
+                results.lock().unwrap()[idx] = Some(result);
+            });
+        }
+    });
+
+    results.into_inner().unwrap().into_iter().map(|result| result.unwrap()).collect()
+}
+
+pub fn to_json(results: &[BatchResult]) -> String {
+    let entries: Vec<String> = results
+        .iter()
+        .map(|result| {
+            format!(
+                "{{\"rom\":{},\"profile\":{},\"frames\":{},\"instructions\":{},\"display_hash\":\"{:08X}\",\"error\":{}}}",
+                json::string(&result.rom_name),
+                json::string(&result.profile),
+                result.frames,
+                result.instructions,
+                result.display_hash,
+                match &result.error {
+                    Some(error) => json::string(error),
+                    None => String::from("null"),
+                }
+            )
+        })
+        .collect();
+    format!("[{}]", entries.join(","))
+}
+
+pub fn to_csv(results: &[BatchResult]) -> String {
+    let mut lines = vec![String::from("rom,profile,frames,instructions,display_hash,error")];
+    for result in results {
+        lines.push(format!(
+            "{},{},{},{},{:08X},{}",
+            csv_field(&result.rom_name),
+            csv_field(&result.profile),
+            result.frames,
+            result.instructions,
+            result.display_hash,
+            csv_field(result.error.as_deref().unwrap_or(""))
+        ));
+    }
+    lines.join("\n") + "\n"
+}
+
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn results_match_a_single_threaded_run() {
+        let roms = vec![
+            (String::from("loop"), vec![0xC0, 0xFF, 0xD0, 0x05, 0x12, 0x00]),
+            (String::from("bad"), vec![0x00, 0x00]),
+        ];
+        let profiles: Vec<(String, Quirks)> = Quirks::PROFILES.iter().map(|(n, q)| (n.to_string(), *q)).collect();
+        let jobs = BatchJob::cross(&roms, &profiles);
+        let config = BatchConfig { frames: 50, threads: 4, seed: 3 };
+
+        let results = run_batch(&jobs, &config);
+        assert_eq!(results.len(), 6);
+        for (job, result) in jobs.iter().zip(results.iter()) {
+            assert_eq!(*result, run_job(job, &config));
+        }
+        assert_eq!(results[0].instructions, 500);
+        assert_eq!(results[3].error, Some(String::from("0 opcode not handled")));
+        assert!(to_csv(&results).starts_with("rom,profile,frames,instructions,display_hash,error\nloop,default,50,500,"));
+    }
+
+    #[test]
+    fn frames_end_while_waiting_for_a_key() {
+        let job = BatchJob { rom_name: String::from("wait"), rom: vec![0xF0, 0x0A], profile: String::from("default"), quirks: Quirks::DEFAULT };
+        let result = run_job(&job, &BatchConfig { frames: 20, threads: 1, seed: 1 });
+        assert_eq!((result.frames, result.instructions, result.error), (20, 20, None));
+    }
+
+}
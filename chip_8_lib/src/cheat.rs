@@ -0,0 +1,246 @@
+use crate::checksum;
+use crate::cpu::Cpu;
+
+const HEADER: &str = "chip8-cheats 1";
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CheatTiming {
+    // Written once, just after the ROM is loaded
+    OnLoad,
+
+    // Written at the start of every frame
+    EveryFrame,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Comparison {
+    Equal,
+    NotEqual,
+    Less,
+    Greater,
+}
+
+// A cheat only applies while memory[address] compares to value this way
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CheatCondition {
+    pub address: u16,
+    pub comparison: Comparison,
+    pub value: u8,
+}
+
+// Writes value to address, for example to keep a game's lives counter topped up
+#[derive(Clone, Debug, PartialEq)]
+pub struct Cheat {
+    // The ROM the cheat is for
+    pub rom_crc32: u32,
+    pub timing: CheatTiming,
+    pub address: u16,
+    pub value: u8,
+    pub condition: Option<CheatCondition>,
+    pub description: String,
+    pub enabled: bool,
+}
+
+// Cheats are saved as text, in sections for each ROM:
+//
+//     chip8-cheats 1
+//     rom 7D75A857
+//     frame 2F0 09 Infinite lives
+//     frame 2F1 63 if 2F1 < 0A Score never drops below 10
+//     load 2F2 03 Start on level 3
+//
+// Each cheat is `load` or `frame`, a hexadecimal address and value, an optional condition
+// comparing a byte of memory with ==, !=, < or >, and a description. Blank lines and lines
+// starting with # are ignored.
+pub fn parse(text: &str) -> Result<Vec<Cheat>, String> {
+    let mut lines = text
+        .lines()
+        .enumerate()
+        .map(|(idx, line)| (idx, line.trim()))
+        .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'));
+
+    match lines.next() {
+        Some((_, line)) if line == HEADER => {}
+        _ => return Err(format!("expected `{}` on the first line", HEADER)),
+    }
+
+    let mut cheats = vec![];
+    let mut rom_crc32 = None;
+    for (idx, line) in lines {
+        let error = |message: &str| format!("line {}: {}", idx + 1, message);
+        let words: Vec<&str> = line.split_whitespace().collect();
+
+        let timing = match words[0] {
+            "rom" => {
+                let crc32 = words.get(1).and_then(|crc32| u32::from_str_radix(crc32, 16).ok());
+                rom_crc32 = Some(crc32.ok_or_else(|| error("bad ROM checksum"))?);
+                continue;
+            }
+            "load" => CheatTiming::OnLoad,
+            "frame" => CheatTiming::EveryFrame,
+            _ => return Err(error("expected `rom`, `load` or `frame`")),
+        };
+        let rom_crc32 = rom_crc32.ok_or_else(|| error("cheats have to come after a `rom` line"))?;
+
+        let address = words.get(1).and_then(|address| parse_address(address)).ok_or_else(|| error("bad address"))?;
+        let value = words.get(2).and_then(|value| u8::from_str_radix(value, 16).ok()).ok_or_else(|| error("bad value"))?;
+
+        let mut rest = &words[3.min(words.len())..];
+        let mut condition = None;
+        if rest.first() == Some(&"if") {
+            condition = match rest {
+                [_, address, comparison, value, ..] => {
+                    let address = parse_address(address);
+                    let comparison = match *comparison {
+                        "==" => Some(Comparison::Equal),
+                        "!=" => Some(Comparison::NotEqual),
+                        "<" => Some(Comparison::Less),
+                        ">" => Some(Comparison::Greater),
+                        _ => None,
+                    };
+                    let value = u8::from_str_radix(value, 16).ok();
+                    match (address, comparison, value) {
+                        (Some(address), Some(comparison), Some(value)) => Some(CheatCondition { address, comparison, value }),
+                        _ => None,
+                    }
+                }
+                _ => None,
+            };
+            if condition.is_none() {
+                return Err(error("expected a condition like `if 2F0 < 0A`"));
+            }
+            rest = &rest[4..];
+        }
+
+        cheats.push(Cheat {
+            rom_crc32,
+            timing,
+            address,
+            value,
+            condition,
+            description: rest.join(" "),
+            enabled: true,
+        });
+    }
+    Ok(cheats)
+}
+
+pub fn to_text(cheats: &[Cheat]) -> String {
+    let mut lines = vec![String::from(HEADER)];
+    let mut rom_crc32 = None;
+    for cheat in cheats {
+        if rom_crc32 != Some(cheat.rom_crc32) {
+            lines.push(format!("rom {:08X}", cheat.rom_crc32));
+            rom_crc32 = Some(cheat.rom_crc32);
+        }
+        lines.push(cheat.to_line());
+    }
+    lines.join("\n") + "\n"
+}
+
+// The cheats that were written for this ROM
+pub fn for_rom(cheats: Vec<Cheat>, rom: &[u8]) -> Vec<Cheat> {
+    let crc32 = checksum::crc32(rom);
+    cheats.into_iter().filter(|cheat| cheat.rom_crc32 == crc32).collect()
+}
+
+// Applies the enabled cheats with this timing
+pub fn apply(cheats: &[Cheat], timing: CheatTiming, cpu: &mut Cpu) {
+    for cheat in cheats.iter().filter(|cheat| cheat.enabled && cheat.timing == timing) {
+        cheat.apply(cpu);
+    }
+}
+
+fn parse_address(text: &str) -> Option<u16> {
+    u16::from_str_radix(text, 16).ok().filter(|address| *address < 0x1000)
+}
+
+impl Cheat {
+    // The cheat as it appears in a cheat file, e.g. `frame 2F0 09 Infinite lives`
+    pub fn to_line(&self) -> String {
+        let timing = match self.timing {
+            CheatTiming::OnLoad => "load",
+            CheatTiming::EveryFrame => "frame",
+        };
+        let mut line = format!("{} {:03X} {:02X}", timing, self.address, self.value);
+        if let Some(condition) = self.condition {
+            let comparison = match condition.comparison {
+                Comparison::Equal => "==",
+                Comparison::NotEqual => "!=",
+                Comparison::Less => "<",
+                Comparison::Greater => ">",
+            };
+            line.push_str(&format!(" if {:03X} {} {:02X}", condition.address, comparison, condition.value));
+        }
+        if !self.description.is_empty() {
+            line.push(' ');
+            line.push_str(&self.description);
+        }
+        line
+    }
+
+    pub fn condition_holds(&self, cpu: &Cpu) -> bool {
+        match self.condition {
+            None => true,
+            Some(condition) => {
+                let current = cpu.memory[condition.address as usize];
+                match condition.comparison {
+                    Comparison::Equal => current == condition.value,
+                    Comparison::NotEqual => current != condition.value,
+                    Comparison::Less => current < condition.value,
+                    Comparison::Greater => current > condition.value,
+                }
+            }
+        }
+    }
+
+    pub fn apply(&self, cpu: &mut Cpu) {
+        if self.condition_holds(cpu) {
+            cpu.poke_if_changed(self.address, self.value);
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn text_round_trips() {
+        let text = "chip8-cheats 1\n# PONG\nrom 0000ABCD\nframe 2F0 09 Infinite lives\nframe 2F1 63 if 2F1 < 0A Score never drops below 10\nload 2F2 03\nrom 12345678\nframe 300 00\n";
+        let cheats = parse(text).unwrap();
+        assert_eq!(cheats.len(), 4);
+        assert_eq!(cheats[1].condition, Some(CheatCondition { address: 0x2F1, comparison: Comparison::Less, value: 0x0A }));
+        assert_eq!(cheats[1].description, "Score never drops below 10");
+        assert_eq!(cheats[2].timing, CheatTiming::OnLoad);
+        assert_eq!(to_text(&cheats), text.replace("# PONG\n", ""));
+
+        assert!(parse("chip8-cheats 1\nframe 2F0 09\n").unwrap_err().contains("after a `rom` line"));
+        assert!(parse("chip8-cheats 1\nrom 1\nframe 2F0 09 if 2F0 ~ 1\n").is_err());
+    }
+
+    #[test]
+    fn applies_enabled_cheats_whose_condition_holds() {
+        let rom = vec![0x12, 0x00];
+        let text = format!("chip8-cheats 1\nrom {:08X}\nframe 300 05 if 301 == 01\nload 302 07\nrom 0\nframe 303 01\n", checksum::crc32(&rom));
+        let mut cheats = for_rom(parse(&text).unwrap(), &rom);
+        assert_eq!(cheats.len(), 2);
+
+        let mut cpu = Cpu::new();
+        cpu.load_game(rom);
+        apply(&cheats, CheatTiming::OnLoad, &mut cpu);
+        apply(&cheats, CheatTiming::EveryFrame, &mut cpu);
+        assert_eq!(cpu.memory[0x300..0x304], [0, 0, 7, 0]);
+
+        cpu.memory[0x301] = 1;
+        apply(&cheats, CheatTiming::EveryFrame, &mut cpu);
+        assert_eq!(cpu.memory[0x300], 5);
+
+        cpu.memory[0x300] = 0;
+        cheats[0].enabled = false;
+        apply(&cheats, CheatTiming::EveryFrame, &mut cpu);
+        assert_eq!(cpu.memory[0x300], 0);
+    }
+
+}
@@ -0,0 +1,133 @@
+use std::collections::BTreeMap;
+
+use crate::cpu::Cpu;
+
+// How to compare each candidate address against its value at the last snapshot
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SearchFilter {
+    Unchanged,
+    Changed,
+    Increased,
+    Decreased,
+    EqualTo(u8),
+}
+
+impl SearchFilter {
+    // Parses "unchanged", "changed", "increased", "decreased" or "equals N" (decimal, or
+    // hexadecimal with 0x)
+    pub fn parse(text: &str) -> Result<SearchFilter, String> {
+        let words: Vec<&str> = text.split_whitespace().collect();
+        match words.as_slice() {
+            ["unchanged"] => Ok(SearchFilter::Unchanged),
+            ["changed"] => Ok(SearchFilter::Changed),
+            ["increased"] => Ok(SearchFilter::Increased),
+            ["decreased"] => Ok(SearchFilter::Decreased),
+            ["equals", value] => {
+                let parsed = match value.strip_prefix("0x") {
+                    Some(hex) => u8::from_str_radix(hex, 16),
+                    None => value.parse(),
+                };
+                parsed.map(SearchFilter::EqualTo).map_err(|_| format!("{} isn't a byte", value))
+            }
+            _ => Err(format!("unknown search `{}`, expected unchanged, changed, increased, decreased or equals N", text)),
+        }
+    }
+
+    fn matches(&self, previous: u8, current: u8) -> bool {
+        match self {
+            SearchFilter::Unchanged => current == previous,
+            SearchFilter::Changed => current != previous,
+            SearchFilter::Increased => current > previous,
+            SearchFilter::Decreased => current < previous,
+            SearchFilter::EqualTo(value) => current == *value,
+        }
+    }
+}
+
+// Finds the addresses a game keeps things like its score or lives in, the way cheat finders in
+// other emulators do. Start a search, play a little, filter by how the value you're after
+// changed, and repeat until only a few addresses are left. Those can then be frozen.
+#[derive(Clone, Debug, Default)]
+pub struct CheatSearch {
+    // Memory when the search started or was last filtered
+    pub snapshot: Vec<u8>,
+
+    // Addresses that have matched every filter so far
+    pub candidates: Vec<u16>,
+
+    // Addresses written with a fixed value every frame, by apply()
+    pub frozen: BTreeMap<u16, u8>,
+}
+
+impl CheatSearch {
+    // Every address in memory is a candidate to begin with
+    pub fn new(cpu: &Cpu) -> CheatSearch {
+        CheatSearch {
+            snapshot: cpu.memory.to_vec(),
+            candidates: (0..cpu.memory.len() as u16).collect(),
+            frozen: BTreeMap::new(),
+        }
+    }
+
+    // Starts over with every address as a candidate, keeping frozen addresses
+    pub fn restart(&mut self, cpu: &Cpu) {
+        let frozen = std::mem::take(&mut self.frozen);
+        *self = CheatSearch { frozen, ..CheatSearch::new(cpu) };
+    }
+
+    // Drops the candidates that don't match, then takes a new snapshot to compare the next
+    // filter against. Returns how many candidates are left.
+    pub fn filter(&mut self, cpu: &Cpu, filter: SearchFilter) -> usize {
+        let snapshot = &self.snapshot;
+        self.candidates.retain(|address| {
+            let address = *address as usize;
+            filter.matches(snapshot[address], cpu.memory[address])
+        });
+        self.snapshot = cpu.memory.to_vec();
+        self.candidates.len()
+    }
+
+    pub fn freeze(&mut self, address: u16, value: u8) {
+        self.frozen.insert(address & 0x0FFF, value);
+    }
+
+    pub fn unfreeze(&mut self, address: u16) {
+        self.frozen.remove(&(address & 0x0FFF));
+    }
+
+    // Writes the frozen values back, call once a frame
+    pub fn apply(&self, cpu: &mut Cpu) {
+        for (address, value) in self.frozen.iter() {
+            cpu.poke_if_changed(*address, *value);
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn narrows_down_to_a_counter() {
+        // Counts up in V0 and stores it at 0x300 every time round the loop
+        let rom = vec![0xA3, 0x00, 0x70, 0x01, 0xF0, 0x55, 0x12, 0x02];
+        let mut cpu = Cpu::new();
+        cpu.load_game(rom);
+        let mut search = CheatSearch::new(&cpu);
+
+        cpu.run_frame_cycles(3);
+        assert!(search.filter(&cpu, SearchFilter::Changed) < 10);
+        cpu.run_frame_cycles(3);
+        search.filter(&cpu, SearchFilter::Increased);
+        search.filter(&cpu, SearchFilter::Unchanged);
+        assert_eq!(search.filter(&cpu, SearchFilter::parse("equals 2").unwrap()), 1);
+        assert_eq!(search.candidates, vec![0x300]);
+
+        search.freeze(0x300, 99);
+        search.apply(&mut cpu);
+        assert_eq!(cpu.memory[0x300], 99);
+        assert!(SearchFilter::parse("equals 256").is_err());
+    }
+
+}
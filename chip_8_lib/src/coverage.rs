@@ -0,0 +1,322 @@
+use std::collections::BTreeMap;
+
+use crate::cpu::PROGRAM_START;
+use crate::disassembler;
+use crate::rom_info::RomInfo;
+
+const MEMORY_SIZE: usize = 4096;
+
+// Tracks which instructions were executed, and for the skip instructions (3xkk, 4xkk, 5xy0,
+// 9xy0, Ex9E and ExA1) whether they were seen both skipping and not skipping.
+// Attach one to Cpu::coverage and every emulate_cycle() call is recorded.
+pub struct Coverage {
+    pub executed: Vec<bool>,
+
+    // Skip instructions that skipped the next instruction
+    pub skipped: Vec<bool>,
+
+    // Skip instructions that fell through to the next instruction
+    pub not_skipped: Vec<bool>,
+}
+
+impl Default for Coverage {
+    fn default() -> Self {
+        Coverage::new()
+    }
+}
+
+fn is_skip(opcode: u16) -> bool {
+    matches!(
+        disassembler::opcode_pattern(opcode),
+        "3xkk" | "4xkk" | "5xy0" | "9xy0" | "Ex9E" | "ExA1"
+    )
+}
+
+// Whether a source line was executed, and for skip instructions whether they skipped and didn't skip
+type LineCoverage = (bool, Option<(bool, bool)>);
+
+// A line of the annotated listing
+enum Line {
+    Instruction(usize),
+    Data(usize, usize),
+}
+
+impl Coverage {
+    pub fn new() -> Coverage {
+        Coverage {
+            executed: vec![false; MEMORY_SIZE],
+            skipped: vec![false; MEMORY_SIZE],
+            not_skipped: vec![false; MEMORY_SIZE],
+        }
+    }
+
+    // Called by the Cpu after it executed the opcode at pc, next_pc is where it went next
+    pub fn record(&mut self, pc: u16, opcode: u16, next_pc: u16) {
+        let address = pc as usize % MEMORY_SIZE;
+        self.executed[address] = true;
+        if is_skip(opcode) {
+            // The program counter is 12 bits, so a skip at the end of memory wraps to the start
+            if next_pc == (pc + 4) & 0x0FFF {
+                self.skipped[address] = true;
+            } else {
+                self.not_skipped[address] = true;
+            }
+        }
+    }
+
+    // Splits the ROM into instructions and data. Instructions are everything that was executed,
+    // plus code the static analysis can reach that never ran.
+    fn lines(&self, rom: &[u8]) -> Vec<Line> {
+        let rom_end = (PROGRAM_START + rom.len()).min(MEMORY_SIZE);
+        let mut reachable = [false; MEMORY_SIZE];
+        for (start, end) in RomInfo::analyze(rom).code_ranges {
+            for address in (start as usize..end as usize).step_by(2) {
+                reachable[address] = true;
+            }
+        }
+
+        let mut lines = vec![];
+        let mut address = PROGRAM_START;
+        while address < rom_end {
+            let is_instruction = address + 1 < rom_end
+                && (self.executed[address] || (reachable[address] && !self.executed[address + 1]));
+            if is_instruction {
+                lines.push(Line::Instruction(address));
+                address += 2;
+                continue;
+            }
+
+            match lines.last_mut() {
+                Some(Line::Data(start, len)) if *start + *len == address && *len < 8 => *len += 1,
+                _ => lines.push(Line::Data(address, 1)),
+            }
+            address += 1;
+        }
+        lines
+    }
+
+    fn branch_status(&self, address: usize) -> &'static str {
+        match (self.skipped[address], self.not_skipped[address]) {
+            (true, true) => "both branches",
+            (true, false) => "always skipped",
+            (false, true) => "never skipped",
+            (false, false) => "",
+        }
+    }
+
+    // Disassembly of the ROM with every instruction marked as executed (*) or not (-)
+    pub fn listing(&self, rom: &[u8]) -> String {
+        let memory = memory_with(rom);
+        let mut output = vec![];
+        let mut instructions = 0;
+        let mut executed = 0;
+        let mut branches = 0;
+        let mut both_branches = 0;
+
+        for line in self.lines(rom) {
+            match line {
+                Line::Instruction(address) => {
+                    let opcode = (memory[address] as u16) << 8 | memory[address + 1] as u16;
+                    instructions += 1;
+                    if self.executed[address] {
+                        executed += 1;
+                    }
+                    let mut status = "";
+                    if is_skip(opcode) && self.executed[address] {
+                        branches += 1;
+                        if self.skipped[address] && self.not_skipped[address] {
+                            both_branches += 1;
+                        }
+                        status = self.branch_status(address);
+                    }
+                    output.push(format!(
+                        "{} 0x{:03X}  {:04X}  {:<16} {}",
+                        if self.executed[address] { '*' } else { '-' },
+                        address,
+                        opcode,
+                        disassembler::disassemble(opcode).trim(),
+                        status
+                    ).trim_end().to_string());
+                }
+                Line::Data(start, len) => {
+                    let bytes: Vec<String> = memory[start..start + len].iter().map(|b| format!("{:02X}", b)).collect();
+                    output.push(format!("  0x{:03X}  {}", start, bytes.join(" ")));
+                }
+            }
+        }
+
+        output.push(String::new());
+        output.push(format!(
+            "Instructions executed: {}/{} ({:.1}%)",
+            executed,
+            instructions,
+            percent(executed, instructions)
+        ));
+        output.push(format!(
+            "Skips taken both ways: {}/{} ({:.1}%)",
+            both_branches,
+            branches,
+            percent(both_branches, branches)
+        ));
+        output.join("\n")
+    }
+
+    // Coverage in the lcov tracefile format. Without a source map every instruction address is
+    // reported as a line of the ROM file itself.
+    pub fn lcov(&self, rom: &[u8], rom_name: &str, source_map: Option<&SourceMap>) -> String {
+        let memory = memory_with(rom);
+
+        let mut files: BTreeMap<String, BTreeMap<usize, LineCoverage>> = BTreeMap::new();
+        for line in self.lines(rom) {
+            if let Line::Instruction(address) = line {
+                let (file, line_number) = match source_map {
+                    Some(map) => match map.lines.get(&(address as u16)) {
+                        Some((file, line_number)) => (file.clone(), *line_number),
+                        None => continue,
+                    },
+                    None => (rom_name.to_string(), address),
+                };
+
+                let opcode = (memory[address] as u16) << 8 | memory[address + 1] as u16;
+                let branches = if is_skip(opcode) {
+                    Some((self.skipped[address], self.not_skipped[address]))
+                } else {
+                    None
+                };
+
+                // Several instructions can come from one source line (macros), it counts as executed if any of them were
+                let entry = files.entry(file).or_default().entry(line_number).or_insert((false, None));
+                entry.0 |= self.executed[address];
+                entry.1 = match (entry.1, branches) {
+                    (Some((a, b)), Some((c, d))) => Some((a || c, b || d)),
+                    (a, b) => a.or(b),
+                };
+            }
+        }
+
+        let mut output = vec![String::from("TN:")];
+        for (file, lines) in files {
+            output.push(format!("SF:{}", file));
+            let mut branches_found = 0;
+            let mut branches_hit = 0;
+            for (line_number, (executed, branches)) in lines.iter() {
+                if let Some((skipped, not_skipped)) = branches {
+                    for (idx, taken) in [*not_skipped, *skipped].iter().enumerate() {
+                        branches_found += 1;
+                        let count = if !executed {
+                            String::from("-")
+                        } else if *taken {
+                            branches_hit += 1;
+                            String::from("1")
+                        } else {
+                            String::from("0")
+                        };
+                        output.push(format!("BRDA:{},0,{},{}", line_number, idx, count));
+                    }
+                }
+            }
+            output.push(format!("BRF:{}", branches_found));
+            output.push(format!("BRH:{}", branches_hit));
+            for (line_number, (executed, _)) in lines.iter() {
+                output.push(format!("DA:{},{}", line_number, *executed as u8));
+            }
+            output.push(format!("LF:{}", lines.len()));
+            output.push(format!("LH:{}", lines.values().filter(|(executed, _)| *executed).count()));
+            output.push(String::from("end_of_record"));
+        }
+        output.join("\n") + "\n"
+    }
+}
+
+// Maps ROM addresses back to assembler source lines.
+// One entry per line, the address followed by the source file and line number:
+//
+//     0x200 pong.8o 12
+//     0x202 pong.8o 13
+//
+// Blank lines and lines starting with # are ignored.
+pub struct SourceMap {
+    pub lines: BTreeMap<u16, (String, usize)>,
+}
+
+impl SourceMap {
+    pub fn parse(text: &str) -> Result<SourceMap, String> {
+        let mut lines = BTreeMap::new();
+        for (idx, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let parts: Vec<&str> = line.split_whitespace().collect();
+            let parsed = match parts.as_slice() {
+                [address, file, line_number] => {
+                    let address = u16::from_str_radix(address.trim_start_matches("0x"), 16).ok();
+                    let line_number = line_number.parse::<usize>().ok();
+                    address.zip(line_number).map(|(a, l)| (a, file.to_string(), l))
+                }
+                _ => None,
+            };
+            match parsed {
+                Some((address, file, line_number)) => {
+                    lines.insert(address, (file, line_number));
+                }
+                None => return Err(format!("line {}: expected `<address> <file> <line>`", idx + 1)),
+            }
+        }
+        Ok(SourceMap { lines })
+    }
+}
+
+fn memory_with(rom: &[u8]) -> Vec<u8> {
+    let mut memory = vec![0; MEMORY_SIZE];
+    let len = rom.len().min(MEMORY_SIZE - PROGRAM_START);
+    memory[PROGRAM_START..PROGRAM_START + len].copy_from_slice(&rom[..len]);
+    memory
+}
+
+fn percent(count: usize, total: usize) -> f64 {
+    if total == 0 {
+        100.0
+    } else {
+        count as f64 * 100.0 / total as f64
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tracks_skips_both_ways() {
+        let rom = [
+            0x30, 0x00, // 0x200 SE V0 0
+            0x00, 0xE0, // 0x202 CLS
+            0x12, 0x00, // 0x204 JP 0x200
+        ];
+        let mut coverage = Coverage::new();
+        coverage.record(0x200, 0x3000, 0x204);
+        coverage.record(0x204, 0x1200, 0x200);
+
+        let listing = coverage.listing(&rom);
+        assert!(listing.contains("* 0x200  3000  SE V0 0          always skipped"));
+        assert!(listing.contains("- 0x202  00E0  CLS"));
+        assert!(listing.contains("Instructions executed: 2/3"));
+
+        coverage.record(0x200, 0x3000, 0x202);
+        let lcov = coverage.lcov(&rom, "TEST", None);
+        assert!(lcov.contains("SF:TEST\nBRDA:512,0,0,1\nBRDA:512,0,1,1\nBRF:2\nBRH:2\nDA:512,1\nDA:514,0\nDA:516,1\n"));
+
+        coverage.record(0xFFE, 0x3000, 0x002);
+        assert!(coverage.skipped[0xFFE] && !coverage.not_skipped[0xFFE]);
+    }
+
+    #[test]
+    fn parses_source_maps() {
+        let map = SourceMap::parse("# comment\n0x200 pong.8o 12\n\n202 pong.8o 13\n").unwrap();
+        assert_eq!(map.lines.get(&0x202), Some(&(String::from("pong.8o"), 13)));
+        assert!(SourceMap::parse("0x200 pong.8o").is_err());
+    }
+
+}
@@ -1,8 +1,25 @@
+use std::collections::{BTreeSet, VecDeque};
+
+use crate::coverage::Coverage;
+use crate::dirty_rect::DirtyRect;
 use crate::emulate_cycle_error::EmulateCycleError;
+use crate::frame_result::FrameResult;
+use crate::instruction::Instruction;
+use crate::key_event::KeyEvent;
+use crate::key_wait::KeyWait;
+use crate::profiler::Profiler;
+use crate::quirks::Quirks;
+use crate::rng::Rng;
 
 const SCREEN_WIDTH: usize = 64;
 const SCREEN_HEIGHT: usize = 32;
 
+// Programs are loaded into memory starting at 0x200, everything below is reserved for the interpreter
+pub const PROGRAM_START: usize = 0x200;
+
+// Instructions executed per 60hz frame, for a ~600hz cpu. The browser runs its frames with this too.
+pub const CYCLES_PER_FRAME: usize = 10;
+
 
 pub struct Cpu {
     // Memory
@@ -26,6 +43,12 @@ pub struct Cpu {
     // 64x32 pixels
     pub display: [u8; SCREEN_WIDTH * SCREEN_HEIGHT],
 
+    // Incremented whenever the display changes, so frontends can tell when to redraw
+    pub display_generation: u64,
+
+    // The area of the display that changed since take_dirty_rect() was last called
+    pub dirty_rect: Option<DirtyRect>,
+
     // Delay timer
     pub dt: u8,
 
@@ -34,6 +57,47 @@ pub struct Cpu {
 
     // Keyboard
     pub keys: [bool; 16],
+
+    // Key presses and releases waiting to be applied to `keys`, see push_key_event()
+    pub key_events: VecDeque<KeyEvent>,
+
+    // Every key event applied, with the cycle it was applied at, while set. Movies are
+    // recorded from this.
+    pub key_log: Option<Vec<KeyEvent>>,
+
+    // Instructions executed since the Cpu was created
+    pub cycle: u64,
+
+    // Set while Fx0A is waiting for a key
+    pub key_wait: Option<KeyWait>,
+
+    // Which interpreter's behaviour to follow where they differ
+    pub quirks: Quirks,
+
+    // Optional execution profiler, records every executed instruction when set
+    pub profiler: Option<Profiler>,
+
+    // Optional code coverage tracking, records every executed instruction when set
+    pub coverage: Option<Coverage>,
+
+    // Random numbers for Cxkk come from this generator when set, so runs can be reproduced.
+    // Otherwise they come from the operating system (or the browser).
+    pub rng: Option<Rng>,
+
+    // Decoded instructions by address, along with the opcode they were decoded from. One
+    // entry per byte of memory. Writes made by the Cpu drop the entries they overlap, anything
+    // else that changes `memory` has to call invalidate_instruction_cache().
+    pub instruction_cache: Box<[Option<(u16, Instruction)>; 4096]>,
+
+    // Decode every instruction again each cycle when false, this is only useful for benchmarking.
+    // Set it before anything runs, instructions that are already cached are still used.
+    pub use_instruction_cache: bool,
+
+    // run_frame() stops when the program counter reaches one of these addresses
+    pub breakpoints: BTreeSet<u16>,
+
+    // Addresses written to since clear_written() was last called, so debuggers can highlight them
+    pub written: Vec<bool>,
 }
 
 impl Default for Cpu {
@@ -63,6 +127,8 @@ pub const CHIP8_FONT_SET: [u8; 80] = [
 
 
 impl Cpu {
+    pub const FULL_SCREEN: DirtyRect = DirtyRect { x: 0, y: 0, width: SCREEN_WIDTH, height: SCREEN_HEIGHT };
+
     pub fn new() -> Cpu {
         let mut cpu = Cpu {
             memory: [0; 4096],
@@ -72,9 +138,24 @@ impl Cpu {
             stack: [0; 16],
             sp: 0,
             display: [0; SCREEN_WIDTH * SCREEN_HEIGHT],
+            display_generation: 0,
+            // Nothing has drawn the display yet
+            dirty_rect: Some(Cpu::FULL_SCREEN),
             dt: 0,
             st: 0,
-            keys: [false; 16]
+            keys: [false; 16],
+            key_events: VecDeque::new(),
+            key_log: None,
+            cycle: 0,
+            key_wait: None,
+            quirks: Quirks::DEFAULT,
+            profiler: None,
+            coverage: None,
+            rng: None,
+            instruction_cache: Box::new([None; 4096]),
+            use_instruction_cache: true,
+            breakpoints: BTreeSet::new(),
+            written: vec![false; 4096],
         };
         cpu.memory[0..80].clone_from_slice(&CHIP8_FONT_SET[..80]);
         cpu
@@ -84,255 +165,346 @@ impl Cpu {
         for (i, item) in CHIP8_FONT_SET.iter().enumerate() {
             self.memory[i] = *item;
         }
+        self.invalidate_instructions(0, CHIP8_FONT_SET.len());
     }
 
+    // Anything that doesn't fit in memory is ignored
     pub fn load_game(&mut self, data: Vec<u8>) {
-        for (idx, item) in data.iter().enumerate() {
-            self.memory[idx + 512] = *item;
-        }
+        let len = data.len().min(self.memory.len() - PROGRAM_START);
+        self.memory[PROGRAM_START..PROGRAM_START + len].copy_from_slice(&data[..len]);
+        self.invalidate_instructions(PROGRAM_START, len);
     }
 
     pub fn emulate_cycle(&mut self) -> Result<(), EmulateCycleError> {
-        let opcode: u16 = self.fetch_current_opcode();
-        match opcode {
-            0x00E0 => {
+        if !self.key_events.is_empty() {
+            self.apply_key_events();
+        }
+
+        let pc = self.pc;
+        let (opcode, instruction) = self.fetch_instruction();
+
+        let result = self.execute(instruction);
+        // Addresses are 12 bits, so jumps and skips past the end of memory wrap around
+        if self.pc > 0x0FFF {
+            self.pc &= 0x0FFF;
+        }
+        self.cycle += 1;
+        result?;
+
+        // Only instructions that ran are recorded, so a CALL that overflowed the stack doesn't
+        // leave the profiler inside a subroutine the program never entered
+        if self.profiler.is_some() || self.coverage.is_some() {
+            self.record(pc, opcode);
+        }
+
+        Ok(())
+    }
+
+    // Kept out of emulate_cycle(), which only pays for a check when nothing is recording
+    #[cold]
+    #[inline(never)]
+    fn record(&mut self, pc: u16, opcode: u16) {
+        if let Some(profiler) = self.profiler.as_mut() {
+            profiler.record(pc, opcode);
+        }
+        if let Some(coverage) = self.coverage.as_mut() {
+            coverage.record(pc, opcode, self.pc);
+        }
+    }
+
+    // Queues a key press or release, applied before the next instruction or, if the event
+    // has a cycle, before that instruction. Events are applied in order.
+    pub fn push_key_event(&mut self, event: KeyEvent) {
+        self.key_events.push_back(event);
+    }
+
+    // Applies the events that are due. A key only changes once per instruction, so a press
+    // and release that arrive together are seen by at least one instruction.
+    fn apply_key_events(&mut self) {
+        let mut changed = [false; 16];
+        while let Some(event) = self.key_events.front().cloned() {
+            let key = (event.key & 0xF) as usize;
+            if event.cycle.is_some_and(|cycle| cycle > self.cycle) || changed[key] {
+                break;
+            }
+            self.key_events.pop_front();
+            changed[key] = true;
+            self.keys[key] = event.pressed;
+            if let Some(log) = self.key_log.as_mut() {
+                log.push(event.at(self.cycle));
+            }
+        }
+    }
+
+    // The timers count down at 60hz, once per frame
+    pub fn tick_timers(&mut self) {
+        if self.dt > 0 {
+            self.dt -= 1;
+        }
+        if self.st > 0 {
+            self.st -= 1;
+        }
+    }
+
+    // Emulates one frame, CYCLES_PER_FRAME instructions followed by a timer tick
+    pub fn run_frame(&mut self) -> FrameResult {
+        self.run_frame_cycles(CYCLES_PER_FRAME)
+    }
+
+    // Emulates a frame of `cycles` instructions. The frame ends early at a breakpoint, an
+    // error or when waiting for a key. The timers tick at the end unless there was an error.
+    pub fn run_frame_cycles(&mut self, cycles: usize) -> FrameResult {
+        let mut result = FrameResult::Completed;
+        for _ in 0..cycles {
+            let pc = self.pc;
+            if let Err(e) = self.emulate_cycle() {
+                let opcode = self.opcode_at(pc);
+                return FrameResult::Error { pc, opcode, message: e.message };
+            }
+
+            if let Some(wait) = self.key_wait.filter(|wait| wait.pc == self.pc) {
+                result = FrameResult::WaitingForKey { pc, register: wait.register, pressed: wait.pressed };
+                break;
+            }
+            if !self.breakpoints.is_empty() && self.breakpoints.contains(&self.pc) {
+                result = FrameResult::Breakpoint { pc: self.pc };
+                break;
+            }
+        }
+        self.tick_timers();
+        result
+    }
+
+    fn execute(&mut self, instruction: Instruction) -> Result<(), EmulateCycleError> {
+        match instruction {
+            Instruction::Cls => {
                 // 00E0 - CLS
                 // Clear the display.
-                for pixel in self.display.iter_mut() {
-                    *pixel = 0;
+                if self.display.contains(&1) {
+                    for pixel in self.display.iter_mut() {
+                        *pixel = 0;
+                    }
+                    self.display_changed(Cpu::FULL_SCREEN);
                 }
                 self.pc += 2;
             }
-            0x00EE => {
+            Instruction::Ret => {
                 // 00EE - RET
                 // Return from a subroutine.
                 // The interpreter sets the program counter to the address at the top of the stack, then subtracts 1 from the stack pointer.
-                println!("sp: {:X}", self.sp);
-                println!("val: {:X}", self.stack[self.sp as usize]);
-
+                if self.sp == 0 || self.sp as usize >= self.stack.len() {
+                    self.pc += 2;
+                    return Err(EmulateCycleError { message: String::from("stack underflow") });
+                }
                 self.pc = self.stack[self.sp as usize];
                 self.pc += 2;
-
-                self.stack[self.sp as usize] = 0xBEEF;
                 self.sp -= 1;
-            },
-            0x1000 ..= 0x1FFF => {
+            }
+            Instruction::Jp(address) => {
                 // 1nnn - JP addr
                 // Jump to location nnn.
-                self.pc = opcode & 0x0FFF;
-            },
-            0x2000 ..= 0x2FFF => {
+                self.pc = address;
+            }
+            Instruction::Call(address) => {
                 // 2nnn - CALL addr
                 // Call subroutine at nnn.
                 // Increment the stack pointer, put the current program counter on the top of the stack,
                 // then the program counter is then set to nnn.
+                if self.sp as usize + 1 >= self.stack.len() {
+                    self.pc += 2;
+                    return Err(EmulateCycleError { message: format!("stack overflow calling {:X}", address) });
+                }
                 self.sp += 1;
                 self.stack[self.sp as usize] = self.pc;
-                self.pc = opcode & 0x0FFF;
-
-                // TODO better error handling if there was a stack overflow?
-                println!("call subroutine at {:X}", opcode);
-            },
-            0x3000 ..= 0x3FFF => {
+                self.pc = address;
+            }
+            Instruction::SeByte(x, kk) => {
                 // 3xkk - SE Vx, byte
                 // Skip next instruction if Vx = kk.
                 // The interpreter compares register Vx to kk, and if they are equal, increments the program counter by 2.
-                let x = (opcode & 0x0F00) >> 8;
-                let kk = opcode & 0x00FF;
-                if self.v[x as usize] == kk as u8 {
+                if self.v[x as usize] == kk {
                     self.pc += 4;
                 } else {
                     self.pc += 2;
                 }
-            },
-            0x4000..=0x4FFF => {
+            }
+            Instruction::SneByte(x, kk) => {
                 // 4xkk - SNE Vx, byte
                 // Skip next instruction if Vx != kk.
                 //The interpreter compares register Vx to kk, and if they are not equal, increments the program counter by 2.
-                let x = (opcode & 0x0F00) >> 8;
-                let kk = opcode & 0x00FF;
-                if self.v[x as usize] != kk as u8 {
+                if self.v[x as usize] != kk {
                     self.pc += 4;
                 } else {
                     self.pc += 2;
                 }
             }
-            0x5000..=0x5FFF => {
+            Instruction::SeReg(x, y) => {
                 // 5xy0 - SE Vx, Vy
                 // Skip next instruction if Vx = Vy.
-                let x = (opcode & 0x0F00) >> 8;
-                let y = (opcode & 0x00F0) >> 4;
                 if self.v[x as usize] == self.v[y as usize] {
                     self.pc += 4;
                 } else {
                     self.pc += 2;
                 }
             }
-            0x6000 ..= 0x6FFF => {
+            Instruction::LdByte(x, kk) => {
                 // 6xkk - LD Vx, byte
                 // The interpreter puts the value kk into register Vx.
-                let x = (opcode & 0x0F00) >> 8;
-                let kk = opcode & 0x00FF;
-                self.v[x as usize] = kk as u8;
+                self.v[x as usize] = kk;
                 self.pc += 2;
-            },
-            0x7000 ..= 0x7FFF => {
+            }
+            Instruction::AddByte(x, kk) => {
                 // 7xkk - ADD Vx, byte
                 // Set Vx = Vx + kk.
-                let x = ((opcode & 0x0F00) >> 8) as usize;
-                let kk = (opcode & 0x00FF) as u8;
-
-                let (result, _) = self.v[x].overflowing_add(kk);
-                self.v[x] = result;
+                let (result, _) = self.v[x as usize].overflowing_add(kk);
+                self.v[x as usize] = result;
                 self.pc += 2;
-            },
-            0x8000..=0x8FFF => {
-                let x = ((opcode & 0x0F00) >> 8) as usize;
-                let y = ((opcode & 0x00F0) >> 4) as usize;
-                let subcode = opcode & 0x000F;
-                match subcode {
-                    0 => {
-                        // 8xy0 - LD Vx, Vy
-                        // Set Vx = Vy.
-                        self.v[x] = self.v[y];
-                        self.pc += 2;
-                    }
-                    1 => {
-                        // 8xy1 - OR Vx, Vy
-                        // Performs a bitwise OR on the values of Vx and Vy, then stores the result in Vx.
-                        self.v[x] |= self.v[y];
-                        self.pc += 2;
-                    }
-                    2 => {
-                        // 8xy2 - AND Vx, Vy
-                        // Set Vx = Vx AND Vy.
-                        // Performs a bitwise AND on the values of Vx and Vy, then stores the result in Vx.
-                        self.v[x] &= self.v[y];
-                        self.pc += 2;
-                    }
-                    3 => {
-                        // 8xy3 - XOR Vx, Vy
-                        // Set Vx = Vx XOR Vy.
-                        // Performs a bitwise exclusive OR on the values of Vx and Vy, then stores the result in Vx.
-                        self.v[x] ^= self.v[y];
-                        self.pc += 2;
-                    }
-                    4 => {
-                        // 8xy4 - ADD Vx, Vy
-                        // Set Vx = Vx + Vy, set VF = carry.
-                        // The values of Vx and Vy are added together. If the result is greater than 8 bits (i.e., > 255,) VF is set to 1, otherwise 0. Only the lowest 8 bits of the result are kept, and stored in Vx.
-                        let (value, did_overflow) = self.v[x].overflowing_add(self.v[y]);
-                        if did_overflow {
-                            self.v[0xF] = 1;
-                        }
-                        self.v[x] = value;
-                        self.pc += 2;
-                    }
-                    5 => {
-                        // 8xy5 - SUB Vx, Vy
-                        // Set Vx = Vx - Vy, set VF = NOT borrow.
-                        // If Vx > Vy, then VF is set to 1, otherwise 0. Then Vy is subtracted from Vx, and the results stored in Vx.
-                        let (value, _did_overflow) = self.v[x].overflowing_sub(self.v[y]);
-                        if self.v[x] > self.v[y] {
-                            self.v[0xF] = 1;
-                        } else {
-                            self.v[0xF] = 0;
-                        }
-                        self.v[x] = value;
-                        self.pc += 2;
-                    }
-                    6 => {
-                        // 8xy6 - SHR Vx {, Vy}
-                        // Set Vx = Vx SHR 1.
-                        // If the least-significant bit of Vx is 1, then VF is set to 1, otherwise 0. Then Vx is divided by 2.
-                        self.v[0xF] = self.v[x] & 0x1;
-                        self.v[x] >>= 1;
-                        self.pc += 2;
-                    }
-                    7 => {
-                        // 8xy7 - SUBN Vx, Vy
-                        // Set Vx = Vy - Vx, set VF = NOT borrow.
-                        // If Vy > Vx, then VF is set to 1, otherwise 0. Then Vx is subtracted from Vy, and the results stored in Vx.
-                        let (value, did_overflow) = self.v[x].overflowing_sub(self.v[y]);
-                        if did_overflow {
-                            self.v[0xF] = 0;
-                        } else {
-                            self.v[0xF] = 1;
-                        }
-                        self.v[x] = value;
-                        self.pc += 2;
-                    }
-                    0xE => {
-                        // 8xyE - SHL Vx {, Vy}
-                        // Set Vx = Vx SHL 1.
-                        // If the most-significant bit of Vx is 1, then VF is set to 1, otherwise to 0. Then Vx is multiplied by 2.
-                        self.v[0xF] = self.v[x] & 0x80;
-                        self.v[x] <<= 1;
-                        self.pc += 2;
-                    }
-                    _ => {
-                        self.pc += 2;
-                        let error = EmulateCycleError { message: format!("{:X} opcode not handled a", opcode) };
-                        return Err(error);
-                    }
+            }
+            Instruction::LdReg(x, y) => {
+                // 8xy0 - LD Vx, Vy
+                // Set Vx = Vy.
+                self.v[x as usize] = self.v[y as usize];
+                self.pc += 2;
+            }
+            Instruction::Or(x, y) => {
+                // 8xy1 - OR Vx, Vy
+                // Performs a bitwise OR on the values of Vx and Vy, then stores the result in Vx.
+                self.v[x as usize] |= self.v[y as usize];
+                if self.quirks.logic_resets_vf {
+                    self.v[0xF] = 0;
+                }
+                self.pc += 2;
+            }
+            Instruction::And(x, y) => {
+                // 8xy2 - AND Vx, Vy
+                // Set Vx = Vx AND Vy.
+                // Performs a bitwise AND on the values of Vx and Vy, then stores the result in Vx.
+                self.v[x as usize] &= self.v[y as usize];
+                if self.quirks.logic_resets_vf {
+                    self.v[0xF] = 0;
+                }
+                self.pc += 2;
+            }
+            Instruction::Xor(x, y) => {
+                // 8xy3 - XOR Vx, Vy
+                // Set Vx = Vx XOR Vy.
+                // Performs a bitwise exclusive OR on the values of Vx and Vy, then stores the result in Vx.
+                self.v[x as usize] ^= self.v[y as usize];
+                if self.quirks.logic_resets_vf {
+                    self.v[0xF] = 0;
                 }
+                self.pc += 2;
+            }
+            Instruction::AddReg(x, y) => {
+                // 8xy4 - ADD Vx, Vy
+                // Set Vx = Vx + Vy, set VF = carry.
+                // The values of Vx and Vy are added together. If the result is greater than 8 bits (i.e., > 255,) VF is set to 1, otherwise 0. Only the lowest 8 bits of the result are kept, and stored in Vx.
+                // VF is written last, so it holds the flag when it's also the destination
+                let (value, did_overflow) = self.v[x as usize].overflowing_add(self.v[y as usize]);
+                self.v[x as usize] = value;
+                self.v[0xF] = did_overflow as u8;
+                self.pc += 2;
             }
-            0x9000..=0x9FFF => {
+            Instruction::Sub(x, y) => {
+                // 8xy5 - SUB Vx, Vy
+                // Set Vx = Vx - Vy, set VF = NOT borrow.
+                // If Vx >= Vy, then VF is set to 1, otherwise 0. Then Vy is subtracted from Vx, and the results stored in Vx.
+                let (value, did_borrow) = self.v[x as usize].overflowing_sub(self.v[y as usize]);
+                self.v[x as usize] = value;
+                self.v[0xF] = !did_borrow as u8;
+                self.pc += 2;
+            }
+            Instruction::Shr(x, y) => {
+                // 8xy6 - SHR Vx {, Vy}
+                // Set Vx = Vx SHR 1.
+                // If the least-significant bit of Vx is 1, then VF is set to 1, otherwise 0. Then Vx is divided by 2.
+                let value = if self.quirks.shift_uses_vy { self.v[y as usize] } else { self.v[x as usize] };
+                self.v[x as usize] = value >> 1;
+                self.v[0xF] = value & 0x1;
+                self.pc += 2;
+            }
+            Instruction::Subn(x, y) => {
+                // 8xy7 - SUBN Vx, Vy
+                // Set Vx = Vy - Vx, set VF = NOT borrow.
+                // If Vy >= Vx, then VF is set to 1, otherwise 0. Then Vx is subtracted from Vy, and the results stored in Vx.
+                let (value, did_borrow) = self.v[y as usize].overflowing_sub(self.v[x as usize]);
+                self.v[x as usize] = value;
+                self.v[0xF] = !did_borrow as u8;
+                self.pc += 2;
+            }
+            Instruction::Shl(x, y) => {
+                // 8xyE - SHL Vx {, Vy}
+                // Set Vx = Vx SHL 1.
+                // If the most-significant bit of Vx is 1, then VF is set to 1, otherwise to 0. Then Vx is multiplied by 2.
+                let value = if self.quirks.shift_uses_vy { self.v[y as usize] } else { self.v[x as usize] };
+                self.v[x as usize] = value << 1;
+                self.v[0xF] = value >> 7;
+                self.pc += 2;
+            }
+            Instruction::SneReg(x, y) => {
                 // 9xy0 - SNE Vx, Vy
                 // Skip next instruction if Vx != Vy.
-                let x = ((opcode & 0x0F00) >> 8) as usize;
-                let y = ((opcode & 0x00F0) >> 4) as usize;
-                if self.v[x] != self.v[y] {
+                if self.v[x as usize] != self.v[y as usize] {
                     self.pc += 4;
                 } else {
                     self.pc += 2;
                 }
-            },
-            0xA000..=0xAFFF => {
+            }
+            Instruction::LdI(address) => {
                 // Annn - LD I, addr
                 // Set I = nnn.
                 // The value of register I is set to nnn.
-
-                self.i = opcode & 0x0FFF;
+                self.i = address;
                 self.pc += 2;
-            },
-            0xB000..=0xBFFF => {
+            }
+            Instruction::JpV0(address) => {
                 // Bnnn - JP V0, addr
                 // Jump to location nnn + V0.
                 // The program counter is set to nnn plus the value of V0.
-                let address = opcode & 0x0FFF;
-                self.pc = (self.v[0x0] as u16) + address;
+                let register = if self.quirks.jump_uses_vx { ((address & 0x0F00) >> 8) as usize } else { 0x0 };
+                self.pc = (self.v[register] as u16) + address;
             }
-            0xC000..=0xCFFF => {
+            Instruction::Rnd(x, kk) => {
                 // Cxkk - RND Vx, byte
                 // Set Vx = random byte AND kk.
                 // The interpreter generates a random number from 0 to 255, which is then ANDed with the value kk. The results are stored in Vx.
-                let x = (opcode & 0x0F00) >> 8;
-                let kk = (opcode & 0x00FF) as u8;
-
-                let mut buf = [0u8; 1];
-                getrandom::getrandom(&mut buf).unwrap();
-                let random = buf[0];
+                let random = match self.rng.as_mut() {
+                    Some(rng) => rng.next_u8(),
+                    None => {
+                        let mut buf = [0u8; 1];
+                        getrandom::getrandom(&mut buf).unwrap();
+                        buf[0]
+                    }
+                };
 
                 self.v[x as usize] = random & kk;
                 self.pc += 2;
             }
-            0xD000 ..= 0xDFFF => {
+            Instruction::Drw(x, y, height) => {
                 // Dxyn - DRW Vx, Vy, nibble
                 // Display n-byte sprite starting at memory location I at (Vx, Vy), set VF = collision.
-                let x: usize = self.v[((opcode & 0x0F00) >> 8) as usize] as usize;
-                let y: usize = self.v[((opcode & 0x00F0) >> 4) as usize] as usize;
-                let height: usize = (opcode & 0x000F) as usize;
-                let sprite: &[u8] = &self.memory[self.i as usize .. (self.i + height as u16) as usize];
-                let rows = sprite.len();
+                let mut x: usize = self.v[x as usize] as usize;
+                let mut y: usize = self.v[y as usize] as usize;
+                if self.quirks.clip_sprites {
+                    // Only the starting position wraps, the rest of the sprite is clipped
+                    x %= SCREEN_WIDTH;
+                    y %= SCREEN_HEIGHT;
+                }
+                let height: usize = height as usize;
 
                 self.v[0xF] = 0;
 
-                for (j, _) in sprite.iter().enumerate().take(rows) {
-                    let row = &sprite[j];
+                // The pixels drawn are marked dirty together once the sprite is drawn
+                let (mut left, mut top, mut right, mut bottom) = (SCREEN_WIDTH, SCREEN_HEIGHT, 0, 0);
+                for j in 0..height {
+                    let row = self.memory[self.address(j)];
+                    if row == 0 {
+                        continue;
+                    }
                     for i in 0..8 {
                         let new_value = row >> (7 - i) & 0x01;
-                        if new_value == 1 {
+                        let clipped = x + i >= SCREEN_WIDTH || y + j >= SCREEN_HEIGHT;
+                        if new_value == 1 && !(self.quirks.clip_sprites && clipped) {
                             let xi = (x + i) % SCREEN_WIDTH;
                             let yj = (y + j) % SCREEN_HEIGHT;
                             let old_value = self.display[xi + yj * SCREEN_WIDTH] == 1;
@@ -341,131 +513,241 @@ impl Cpu {
                             }
                             let display_value = ((new_value == 1) ^ old_value) as u8;
                             self.display[xi + yj * SCREEN_WIDTH] = display_value;
+                            left = left.min(xi);
+                            top = top.min(yj);
+                            right = right.max(xi + 1);
+                            bottom = bottom.max(yj + 1);
                        }
                     }
                 }
+                if left < right {
+                    self.display_changed(DirtyRect { x: left, y: top, width: right - left, height: bottom - top });
+                }
 
                 self.pc += 2;
             }
-            0xE000 ..= 0xEFFF => {
-                let x = (opcode & 0x0F00) >> 8;
-                let code = opcode & 0x00FF;
-                match code {
-                    0x9E => {
-                        // Ex9E - SKP Vx
-                        // Skip next instruction if key with the value of Vx is pressed.
-                        if self.keys[self.v[x as usize] as usize]{
-                            self.pc += 4;
-                        } else {
-                            self.pc += 2;
-                        }
-                    }
-                    0xA1 => {
-                        // ExA1 - SKNP Vx
-                        // Skip next instruction if key with the value of Vx is not pressed.
-                        // Checks the keyboard, and if the key corresponding to the value of Vx is currently in the up position;
-                        if !self.keys[self.v[x as usize] as usize]{
-                            self.pc += 4;
-                        } else {
-                            self.pc += 2;
-                        }
-                    }
-                    _ => {
-                        self.pc += 2;
-                        let error = EmulateCycleError { message: format!("{:X} opcode not handled b", opcode) };
-                        return Err(error);
-                    }
+            Instruction::Skp(x) => {
+                // Ex9E - SKP Vx
+                // Skip next instruction if key with the value of Vx is pressed.
+                if self.keys[(self.v[x as usize] & 0xF) as usize] {
+                    self.pc += 4;
+                } else {
+                    self.pc += 2;
                 }
             }
-            0xF000 ..= 0xFFFF => {
-                let x = ((opcode & 0x0F00) >> 8) as usize;
-                let code = opcode & 0x00FF;
-                match code {
-                    0x07 => {
-                        // Fx07 - LD Vx, DT
-                        // Set Vx = delay timer value.
-                        self.v[x] = self.dt;
-                    }
-                    0x0A => {
-                        // Fx0A - LD Vx, K
-                        // Wait for a key press, store the value of the key in Vx.
-                        // All execution stops until a key is pressed, then the value of that key is stored in Vx.
-                        for (i, key) in self.keys.iter().enumerate() {
-                            if *key {
-                                self.v[x] = i as u8;
-                                self.pc +=2;
-                            }
-                        }
-                    }
-                    0x15 => {
-                        // Fx15 - LD DT, Vx
-                        // Set delay timer = Vx.
-                        self.dt = self.v[x];
-                    }
-                    0x18 => {
-                        // Fx18 - LD ST, Vx
-                        // Set sound timer = Vx.
-                        self.st = self.v[x];
-                    }
-                    0x1E => {
-                        // Fx1E - ADD I, Vx
-                        // Set I = I + Vx.
-                        self.i += self.v[x] as u16;
-                    }
-                    0x29 => {
-                        // Fx29 - LD F, Vx
-                        // Set I = location of sprite for digit Vx.
-                        self.i = self.v[x] as u16 * 5;
-                    }
-                    0x33 => {
-                        // Fx33 - LD B, Vx
-                        // Store BCD representation of Vx in memory locations I, I+1, and I+2.
-                        self.memory[self.i as usize] = (self.v[x] / 100) as u8;
-                        self.memory[(self.i + 1) as usize] = (self.v[x] / 10) as u8 % 10;
-                        self.memory[(self.i + 2) as usize] = (self.v[x] % 100) as u8 % 10;
-                    }
-                    0x55 => {
-                        // Fx55 - LD [I], Vx
-                        // Store registers V0 through Vx in memory starting at location I.
-                        // The interpreter copies the values of registers V0 through Vx into memory, starting at the address in I.
-                        for offset in 0..=x {
-                            self.memory[(self.i + offset as u16) as usize] = self.v[offset];
-                        }
-                    }
-                    0x65 => {
-                        // Fx65 - LD Vx, [I]
-                        // The interpreter reads values from memory starting at location I into registers V0 through Vx.
-                        for offset in 0..=x {
-                            self.v[offset] = self.memory[(self.i + offset as u16) as usize];
-                        }
-                    }
-                    _ => {
-                        self.pc += 2;
-                        let error = EmulateCycleError { message: format!("{:X} F opcode not handled", opcode) };
-                        return Err(error);
-                    }
+            Instruction::Sknp(x) => {
+                // ExA1 - SKNP Vx
+                // Skip next instruction if key with the value of Vx is not pressed.
+                // Checks the keyboard, and if the key corresponding to the value of Vx is currently in the up position;
+                if !self.keys[(self.v[x as usize] & 0xF) as usize] {
+                    self.pc += 4;
+                } else {
+                    self.pc += 2;
+                }
+            }
+            Instruction::LdVxDt(x) => {
+                // Fx07 - LD Vx, DT
+                // Set Vx = delay timer value.
+                self.v[x as usize] = self.dt;
+                self.pc += 2;
+            }
+            Instruction::LdVxK(x) => {
+                // Fx0A - LD Vx, K
+                // Wait for a key press, store the value of the key in Vx.
+                // All execution stops until a key is pressed, then the value of that key is stored in Vx.
+                // Waiting leaves the program counter where it is, so this runs again next cycle.
+                // Keys held from before have to be released first, and with the wait_for_release
+                // quirk the pressed key has to be released too, like on the COSMAC VIP.
+                let keys = self.keys;
+                let pc = self.pc;
+                let wait = match &mut self.key_wait {
+                    Some(wait) if wait.pc == pc && wait.register == x as usize => wait,
+                    key_wait => key_wait.insert(KeyWait::new(pc, x as usize, &keys)),
+                };
+                if let Some(key) = wait.update(&keys, self.quirks.wait_for_release) {
+                    self.key_wait = None;
+                    self.v[x as usize] = key;
+                    self.pc += 2;
+                }
+            }
+            Instruction::LdDtVx(x) => {
+                // Fx15 - LD DT, Vx
+                // Set delay timer = Vx.
+                self.dt = self.v[x as usize];
+                self.pc += 2;
+            }
+            Instruction::LdStVx(x) => {
+                // Fx18 - LD ST, Vx
+                // Set sound timer = Vx.
+                self.st = self.v[x as usize];
+                self.pc += 2;
+            }
+            Instruction::AddIVx(x) => {
+                // Fx1E - ADD I, Vx
+                // Set I = I + Vx.
+                self.i = self.i.wrapping_add(self.v[x as usize] as u16);
+                self.pc += 2;
+            }
+            Instruction::LdFVx(x) => {
+                // Fx29 - LD F, Vx
+                // Set I = location of sprite for digit Vx.
+                self.i = self.v[x as usize] as u16 * 5;
+                self.pc += 2;
+            }
+            Instruction::LdBVx(x) => {
+                // Fx33 - LD B, Vx
+                // Store BCD representation of Vx in memory locations I, I+1, and I+2.
+                let digits = [self.v[x as usize] / 100, self.v[x as usize] / 10 % 10, self.v[x as usize] % 10];
+                for (offset, digit) in digits.iter().enumerate() {
+                    self.write(self.address(offset), *digit);
+                }
+                self.pc += 2;
+            }
+            Instruction::LdIVx(x) => {
+                // Fx55 - LD [I], Vx
+                // Store registers V0 through Vx in memory starting at location I.
+                // The interpreter copies the values of registers V0 through Vx into memory, starting at the address in I.
+                for offset in 0..=x as usize {
+                    self.write(self.address(offset), self.v[offset]);
+                }
+                if self.quirks.load_store_increments_i {
+                    self.i = self.i.wrapping_add(x as u16 + 1);
                 }
                 self.pc += 2;
             }
-            _ => {
+            Instruction::LdVxI(x) => {
+                // Fx65 - LD Vx, [I]
+                // The interpreter reads values from memory starting at location I into registers V0 through Vx.
+                for offset in 0..=x as usize {
+                    self.v[offset] = self.memory[self.address(offset)];
+                }
+                if self.quirks.load_store_increments_i {
+                    self.i = self.i.wrapping_add(x as u16 + 1);
+                }
                 self.pc += 2;
-                let error = EmulateCycleError { message: format!("{:X} opcode not handled d", opcode) };
+            }
+            Instruction::Unknown(opcode) => {
+                self.pc += 2;
+                let error = EmulateCycleError { message: format!("{:X} opcode not handled", opcode) };
                 return Err(error);
             }
         }
 
-        // Decrease timers
-        if self.dt > 0 {
-            self.dt -= 1;
+        Ok(())
+    }
+
+    // Whether the display changed, and where, since this was last called
+    pub fn take_dirty_rect(&mut self) -> Option<DirtyRect> {
+        self.dirty_rect.take()
+    }
+
+    // Anything that writes to the display without going through emulate_cycle() should call this
+    pub fn display_changed(&mut self, rect: DirtyRect) {
+        self.display_generation += 1;
+        self.dirty_rect = Some(match self.dirty_rect {
+            Some(dirty) => dirty.union(&rect),
+            None => rect,
+        });
+    }
+
+    // The memory address `offset` bytes past I, wrapping around at the end of memory
+    fn address(&self, offset: usize) -> usize {
+        (self.i as usize + offset) % self.memory.len()
+    }
+
+    fn write(&mut self, address: usize, value: u8) {
+        self.memory[address] = value;
+        self.invalidate_instructions(address, 1);
+        if let Some(written) = self.written.get_mut(address) {
+            *written = true;
         }
-        if self.st > 0 {
-            self.st -= 1;
+    }
+
+    // Writes bytes into memory from outside the program, e.g. from a debugger, wrapping around
+    // at the end of memory. Cached instructions are dropped as if the program had written them.
+    pub fn write_memory(&mut self, address: u16, bytes: &[u8]) {
+        for (offset, value) in bytes.iter().enumerate() {
+            self.write((address as usize + offset) % self.memory.len(), *value);
+        }
+    }
+
+    // Writes a byte from outside the program only if it's different, so cheats that hold a
+    // value every frame don't throw the instruction cache away for nothing
+    pub fn poke_if_changed(&mut self, address: u16, value: u8) {
+        let address = address as usize % self.memory.len();
+        if self.memory[address] != value {
+            self.write(address, value);
         }
+    }
 
+    pub fn clear_written(&mut self) {
+        for written in self.written.iter_mut() {
+            *written = false;
+        }
+    }
+
+    // Sets a register by name: v0 to vf, i, pc, sp, dt or st
+    pub fn set_register(&mut self, name: &str, value: u16) -> Result<(), String> {
+        let name = name.to_ascii_lowercase();
+        let byte = || if value <= 0xFF { Ok(value as u8) } else { Err(format!("{} is 8 bits, {} is too big", name, value)) };
+        match name.as_str() {
+            "i" => self.i = value,
+            "pc" if (value as usize) < self.memory.len() => {
+                self.pc = value;
+                self.key_wait = None;
+            }
+            "pc" => return Err(format!("0x{:X} is outside memory", value)),
+            "sp" if (value as usize) < self.stack.len() => self.sp = value as u8,
+            "sp" => return Err(format!("the stack only has {} entries", self.stack.len())),
+            "dt" => self.dt = byte()?,
+            "st" => self.st = byte()?,
+            _ => match name.strip_prefix('v').and_then(|register| usize::from_str_radix(register, 16).ok()) {
+                Some(register) if register < self.v.len() && name.len() == 2 => self.v[register] = byte()?,
+                _ => return Err(format!("unknown register {}", name)),
+            },
+        }
         Ok(())
     }
 
+    // The opcode at the program counter and the instruction it decodes to. With the instruction
+    // cache enabled each address is only decoded again after memory there is written.
+    fn fetch_instruction(&mut self) -> (u16, Instruction) {
+        let pc = self.pc as usize % self.instruction_cache.len();
+        if let Some(cached) = self.instruction_cache[pc] {
+            return cached;
+        }
+        let opcode = self.fetch_current_opcode();
+        let decoded = (opcode, Instruction::decode(opcode));
+        if self.use_instruction_cache {
+            self.instruction_cache[pc] = Some(decoded);
+        }
+        decoded
+    }
+
+    // Drops cached instructions overlapping `len` bytes of memory starting at `address`,
+    // an instruction starting one byte before the write includes the first written byte.
+    fn invalidate_instructions(&mut self, address: usize, len: usize) {
+        let start = address.saturating_sub(1);
+        let end = (address + len).min(self.instruction_cache.len());
+        if start >= end {
+            return;
+        }
+        for cached in self.instruction_cache[start..end].iter_mut() {
+            *cached = None;
+        }
+        // The instruction at the last address wraps around to include the first byte
+        if address == 0 {
+            if let Some(last) = self.instruction_cache.last_mut() {
+                *last = None;
+            }
+        }
+    }
 
+    // Drops every cached instruction. Anything that changes `memory` without going through
+    // write_memory() has to call this, or the instructions that were there keep running.
+    pub fn invalidate_instruction_cache(&mut self) {
+        self.invalidate_instructions(0, self.instruction_cache.len());
+    }
 
     fn fetch_current_opcode(&self) -> u16 {
         // Instructions are 2 bytes but memory locations are only 1 byte.
@@ -489,11 +771,20 @@ impl Cpu {
         // Which in hexadecimal is represented at 0x22F6, the correct merge of [0x22, 0xF6]
 
 
-        let code1: u16 = self.memory[self.pc as usize] as u16;
-        let code2: u16 = self.memory[(self.pc + 1) as usize] as u16;
+        self.opcode_at(self.pc)
+    }
+
+    fn opcode_at(&self, address: u16) -> u16 {
+        let code1: u16 = self.memory[address as usize % self.memory.len()] as u16;
+        let code2: u16 = self.memory[(address as usize + 1) % self.memory.len()] as u16;
         code1 << 8 | code2
     }
 
+    // Whether emulate_cycle() knows how to execute an opcode
+    pub fn supports_opcode(opcode: u16) -> bool {
+        !matches!(Instruction::decode(opcode), Instruction::Unknown(_))
+    }
+
 }
 
 
@@ -505,11 +796,81 @@ mod tests {
     fn addition_overflows() {
         let mut cpu = Cpu::new();
         cpu.v[0] = 255;
-        cpu.memory[0x200] = 0x70 as u8;
+        cpu.memory[0x200] = 0x70;
         cpu.memory[0x201] = 0x02;
 
         cpu.emulate_cycle().unwrap();
         assert_eq!(cpu.v[0], 1);
     }
 
+    #[test]
+    fn drawing_marks_the_display_dirty() {
+        let mut cpu = Cpu::new();
+        cpu.load_game(vec![0x00, 0xE0, 0x60, 0x3F, 0xD0, 0x12]);
+        cpu.take_dirty_rect();
+
+        // Clearing a blank screen changes nothing
+        cpu.emulate_cycle().unwrap();
+        cpu.emulate_cycle().unwrap();
+        assert_eq!((cpu.display_generation, cpu.take_dirty_rect()), (0, None));
+
+        // The top 2 rows of the "0" sprite at x = 63 wrap around to the left edge
+        cpu.emulate_cycle().unwrap();
+        assert_eq!(cpu.take_dirty_rect(), Some(DirtyRect { x: 0, y: 0, width: 64, height: 2 }));
+        assert!(cpu.display_generation > 0);
+    }
+
+    #[test]
+    fn frames_stop_at_breakpoints_and_when_waiting_for_a_key() {
+        let mut cpu = Cpu::new();
+        cpu.load_game(vec![0x60, 0x05, 0xF0, 0x15, 0xF1, 0x0A, 0x12, 0x06]);
+        cpu.breakpoints.insert(0x204);
+
+        assert_eq!(cpu.run_frame(), FrameResult::Breakpoint { pc: 0x204 });
+        assert_eq!(cpu.dt, 4);
+        assert_eq!(cpu.run_frame(), FrameResult::WaitingForKey { pc: 0x204, register: 1, pressed: None });
+        assert_eq!(cpu.dt, 3);
+
+        cpu.keys[7] = true;
+        cpu.breakpoints.clear();
+        assert_eq!(cpu.run_frame(), FrameResult::Completed);
+        assert_eq!((cpu.v[1], cpu.dt), (7, 2));
+    }
+
+    #[test]
+    fn waiting_for_a_key_can_wait_for_it_to_be_released() {
+        let mut cpu = Cpu::new();
+        cpu.quirks = Quirks::COSMAC_VIP;
+        cpu.load_game(vec![0xF1, 0x0A, 0x12, 0x02]);
+        cpu.keys[2] = true;
+
+        // 2 was held before the wait started
+        assert_eq!(cpu.run_frame(), FrameResult::WaitingForKey { pc: 0x200, register: 1, pressed: None });
+        cpu.keys[2] = false;
+        cpu.keys[5] = true;
+        assert_eq!(cpu.run_frame(), FrameResult::WaitingForKey { pc: 0x200, register: 1, pressed: Some(5) });
+        cpu.keys[5] = false;
+        assert_eq!(cpu.run_frame(), FrameResult::Completed);
+        assert_eq!((cpu.v[1], cpu.key_wait), (5, None));
+    }
+
+    #[test]
+    fn returning_with_an_empty_stack_is_an_error() {
+        let mut cpu = Cpu::new();
+        cpu.load_game(vec![0x00, 0xEE]);
+
+        let error = cpu.emulate_cycle().unwrap_err();
+        assert_eq!(error.message, "stack underflow");
+        assert_eq!((cpu.pc, cpu.sp), (0x202, 0));
+    }
+
+    #[test]
+    fn poking_the_same_value_leaves_memory_unwritten() {
+        let mut cpu = Cpu::new();
+        cpu.poke_if_changed(0x300, 0);
+        assert!(!cpu.written[0x300]);
+        cpu.poke_if_changed(0x300, 7);
+        assert_eq!((cpu.memory[0x300], cpu.written[0x300]), (7, true));
+    }
+
 }
@@ -0,0 +1,162 @@
+const WIDTH: usize = 64;
+const HEIGHT: usize = 32;
+
+// Post-processing that makes the display look like it's on a CRT, drawn on the CPU so it
+// works anywhere. Each effect's strength is between 0 (off) and 1:
+//
+// - scanlines darken the bottom rows of every display pixel
+// - gaps darken the right column of every display pixel, like the mask between phosphors
+// - bloom lets lit pixels glow into their neighbours and over the dark lines
+// - curvature bulges the picture out like the glass of a tube, leaving the corners black
+//
+// Scanlines and gaps need room inside each display pixel, so they only show at a scale of 3
+// or more.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Crt {
+    pub scanlines: f32,
+    pub gaps: f32,
+    pub bloom: f32,
+    pub curvature: f32,
+}
+
+impl Crt {
+    // What `crt` turns on, and the strength of an effect named without a value
+    pub const PRESET: Crt = Crt { scanlines: 0.5, gaps: 0.3, bloom: 0.4, curvature: 0.1 };
+
+    // Parses "off", "crt" for the preset, or effects like "scanlines=0.6,bloom". Effects that
+    // aren't named are off.
+    pub fn parse(text: &str) -> Result<Crt, String> {
+        match text.trim() {
+            "off" => return Ok(Crt::default()),
+            "crt" => return Ok(Crt::PRESET),
+            _ => {}
+        }
+
+        let mut crt = Crt::default();
+        for effect in text.split(',') {
+            let mut parts = effect.trim().splitn(2, '=');
+            let name = parts.next().unwrap_or("");
+            let (field, preset) = match name {
+                "scanlines" => (&mut crt.scanlines, Crt::PRESET.scanlines),
+                "gaps" => (&mut crt.gaps, Crt::PRESET.gaps),
+                "bloom" => (&mut crt.bloom, Crt::PRESET.bloom),
+                "curvature" => (&mut crt.curvature, Crt::PRESET.curvature),
+                _ => return Err(format!("unknown effect `{}`, expected scanlines, gaps, bloom or curvature", name)),
+            };
+            *field = match parts.next().map(str::parse::<f32>) {
+                None => preset,
+                Some(Ok(value)) if (0.0..=1.0).contains(&value) => value,
+                Some(_) => return Err(format!("{} needs a strength between 0 and 1", name)),
+            };
+        }
+        Ok(crt)
+    }
+
+    pub fn is_off(&self) -> bool {
+        *self == Crt::default()
+    }
+
+    // Draws the whole image into rgba, from the colour of each display pixel
+    pub fn render(&self, colors: &[[u8; 3]], background: [u8; 3], scale: usize, rgba: &mut [u8]) {
+        let glow = self.glow(colors, background);
+        let (width, height) = (WIDTH * scale, HEIGHT * scale);
+        let scanline_rows = (scale / 4).max(1);
+
+        for (idx, pixel) in rgba.chunks_mut(4).enumerate() {
+            let (x, y) = match self.source(idx % width, idx / width, width, height) {
+                Some(source) => source,
+                None => {
+                    pixel.copy_from_slice(&[0, 0, 0, 255]);
+                    continue;
+                }
+            };
+
+            let mut shade = 1.0;
+            if scale >= 3 {
+                if y % scale >= scale - scanline_rows {
+                    shade *= 1.0 - self.scanlines;
+                }
+                if x % scale == scale - 1 {
+                    shade *= 1.0 - self.gaps;
+                }
+            }
+
+            let cell = x / scale + y / scale * WIDTH;
+            for channel in 0..3 {
+                let value = colors[cell][channel] as f32 * shade + glow[cell][channel] * self.bloom;
+                pixel[channel] = value.round().min(255.0) as u8;
+            }
+            pixel[3] = 255;
+        }
+    }
+
+    // How much brighter than the background each display pixel's 3x3 neighbourhood is on average
+    fn glow(&self, colors: &[[u8; 3]], background: [u8; 3]) -> Vec<[f32; 3]> {
+        if self.bloom == 0.0 {
+            return vec![[0.0; 3]; colors.len()];
+        }
+        let mut glow = vec![[0.0; 3]; colors.len()];
+        for y in 0..HEIGHT {
+            for x in 0..WIDTH {
+                let mut sum = [0.0; 3];
+                for ny in y.saturating_sub(1)..(y + 2).min(HEIGHT) {
+                    for nx in x.saturating_sub(1)..(x + 2).min(WIDTH) {
+                        for (channel, total) in sum.iter_mut().enumerate() {
+                            *total += (colors[nx + ny * WIDTH][channel] as f32 - background[channel] as f32).max(0.0);
+                        }
+                    }
+                }
+                glow[x + y * WIDTH] = [sum[0] / 9.0, sum[1] / 9.0, sum[2] / 9.0];
+            }
+        }
+        glow
+    }
+
+    // The pixel of the flat image that shows at (x, y) on the curved one, or None past the
+    // edge of the tube
+    fn source(&self, x: usize, y: usize, width: usize, height: usize) -> Option<(usize, usize)> {
+        if self.curvature == 0.0 {
+            return Some((x, y));
+        }
+        let nx = (x as f32 + 0.5) / width as f32 * 2.0 - 1.0;
+        let ny = (y as f32 + 0.5) / height as f32 * 2.0 - 1.0;
+        let bulge = 1.0 + self.curvature * (nx * nx + ny * ny);
+        let (sx, sy) = (nx * bulge, ny * bulge);
+        if sx.abs() >= 1.0 || sy.abs() >= 1.0 {
+            return None;
+        }
+        Some((((sx + 1.0) / 2.0 * width as f32) as usize, ((sy + 1.0) / 2.0 * height as f32) as usize))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_effects() {
+        assert_eq!(Crt::parse("crt"), Ok(Crt::PRESET));
+        assert!(Crt::parse("off").unwrap().is_off());
+        assert_eq!(Crt::parse("scanlines=0.25, bloom"), Ok(Crt { scanlines: 0.25, bloom: 0.4, ..Crt::default() }));
+        assert!(Crt::parse("scanlines=2").is_err());
+        assert!(Crt::parse("blur").is_err());
+    }
+
+    #[test]
+    fn darkens_lines_and_curves_the_corners() {
+        let white = [200, 200, 200];
+        let colors = vec![white; WIDTH * HEIGHT];
+        let mut rgba = vec![0; WIDTH * HEIGHT * 16 * 4];
+        let crt = Crt { scanlines: 0.5, gaps: 0.25, ..Crt::default() };
+        crt.render(&colors, [0, 0, 0], 4, &mut rgba);
+        let pixel = |rgba: &[u8], x: usize, y: usize| rgba[(x + y * WIDTH * 4) * 4];
+        assert_eq!((pixel(&rgba, 0, 0), pixel(&rgba, 0, 3), pixel(&rgba, 3, 0), pixel(&rgba, 3, 3)), (200, 100, 150, 75));
+
+        let crt = Crt { curvature: 0.2, bloom: 0.5, ..Crt::default() };
+        crt.render(&colors, [0, 0, 0], 4, &mut rgba);
+        assert_eq!(pixel(&rgba, 0, 0), 0);
+        assert_eq!(pixel(&rgba, WIDTH * 2, HEIGHT * 2), 255);
+    }
+
+}
@@ -0,0 +1,222 @@
+use crate::cpu::Cpu;
+use crate::disassembler;
+use crate::instruction::Instruction;
+use crate::json;
+
+// Memory is shown to debuggers a page at a time
+pub const PAGE_SIZE: usize = 256;
+
+// A subroutine call that hasn't returned yet
+#[derive(Clone, Debug, PartialEq)]
+pub struct StackFrame {
+    // 1 for the outermost call
+    pub depth: usize,
+
+    // Address of the CALL instruction, this is what's on the stack
+    pub call_site: u16,
+
+    // Where RET will continue from
+    pub return_address: u16,
+
+    // The subroutine that was called, if the call site still holds a CALL
+    pub subroutine: Option<u16>,
+
+    // Disassembly of the call site
+    pub call: String,
+}
+
+// The calls that haven't returned, from the outermost to the innermost
+pub fn call_stack(cpu: &Cpu) -> Vec<StackFrame> {
+    let depth = (cpu.sp as usize).min(cpu.stack.len() - 1);
+    (1..=depth)
+        .map(|depth| {
+            let call_site = cpu.stack[depth];
+            let address = call_site as usize % cpu.memory.len();
+            let opcode = (cpu.memory[address] as u16) << 8 | cpu.memory[(address + 1) % cpu.memory.len()] as u16;
+            let subroutine = match Instruction::decode(opcode) {
+                Instruction::Call(subroutine) => Some(subroutine),
+                _ => None,
+            };
+            StackFrame {
+                depth,
+                call_site,
+                return_address: call_site.wrapping_add(2),
+                subroutine,
+                call: disassembler::disassemble(opcode).trim().to_string(),
+            }
+        })
+        .collect()
+}
+
+// Everything a debugger shows about the machine, as JSON so frontends don't depend on any
+// particular page layout:
+//
+//   pc, i, sp, dt, st, display_generation
+//   v           the 16 registers
+//   stack       call sites, from the bottom of the stack to the top
+//   call_stack  each call with its depth, call_site, return_address, subroutine and the
+//               disassembled call
+//   keys        whether each key is pressed
+//   key_wait    null, or the register Fx0A is waiting to store a key in and the key that was
+//               pressed if it's waiting for it to be released
+//   breakpoints addresses
+//   disassembly `lines` instructions from the program counter on, each with its address,
+//               opcode, text and whether there's a breakpoint on it
+pub fn state_json(cpu: &Cpu, lines: usize) -> String {
+    let stack_depth = (cpu.sp as usize).min(cpu.stack.len() - 1);
+    let call_stack = call_stack(cpu).into_iter().map(|frame| {
+        format!(
+            "{{\"depth\":{},\"call_site\":{},\"return_address\":{},\"subroutine\":{},\"call\":{}}}",
+            frame.depth,
+            frame.call_site,
+            frame.return_address,
+            frame.subroutine.map(|address| address.to_string()).unwrap_or_else(|| String::from("null")),
+            json::string(&frame.call)
+        )
+    });
+    let disassembly = (0..lines)
+        .map(|line| cpu.pc as usize + line * 2)
+        .take_while(|address| address + 1 < cpu.memory.len())
+        .map(|address| {
+            let opcode = (cpu.memory[address] as u16) << 8 | cpu.memory[address + 1] as u16;
+            format!(
+                "{{\"address\":{},\"opcode\":{},\"text\":{},\"breakpoint\":{}}}",
+                address,
+                opcode,
+                json::string(disassembler::disassemble(opcode).trim()),
+                cpu.breakpoints.contains(&(address as u16))
+            )
+        });
+
+    let key_wait = match cpu.key_wait {
+        Some(wait) => format!(
+            "{{\"register\":{},\"pressed\":{}}}",
+            wait.register,
+            wait.pressed.map(|key| key.to_string()).unwrap_or_else(|| String::from("null"))
+        ),
+        None => String::from("null"),
+    };
+
+    format!(
+        "{{\"pc\":{},\"i\":{},\"sp\":{},\"dt\":{},\"st\":{},\"display_generation\":{},\"v\":{},\"stack\":{},\"call_stack\":{},\"keys\":{},\"key_wait\":{},\"breakpoints\":{},\"disassembly\":{}}}",
+        cpu.pc,
+        cpu.i,
+        cpu.sp,
+        cpu.dt,
+        cpu.st,
+        cpu.display_generation,
+        json::array(cpu.v.iter()),
+        json::array(cpu.stack[1..=stack_depth].iter()),
+        json::array(call_stack),
+        json::array(cpu.keys.iter()),
+        key_wait,
+        json::array(cpu.breakpoints.iter()),
+        json::array(disassembly)
+    )
+}
+
+// One page of memory as {"page", "address", "bytes", "written"}, or None past the end of
+// memory. written lists the addresses on the page written since Cpu::clear_written().
+pub fn memory_page_json(cpu: &Cpu, page: usize) -> Option<String> {
+    let start = page * PAGE_SIZE;
+    let bytes = cpu.memory.get(start..start + PAGE_SIZE)?;
+    let written = (start..start + PAGE_SIZE).filter(|address| cpu.written[*address]);
+    Some(format!(
+        "{{\"page\":{},\"address\":{},\"bytes\":{},\"written\":{}}}",
+        page,
+        start,
+        json::array(bytes.iter()),
+        json::array(written)
+    ))
+}
+
+// Hex and ASCII view of len bytes from start, 16 to a line. Each byte is marked with `>` if the
+// program counter points at it, `@` if I does and `*` if it was written since clear_written():
+//
+//     0x200:>60 2A@A2 0A*00 ...  `*...
+pub fn hex_dump(cpu: &Cpu, start: usize, len: usize) -> String {
+    let mut lines = vec![];
+    let end = (start + len).min(cpu.memory.len());
+    let mut address = start - start % 16;
+    while address < end {
+        let row = address..(address + 16).min(end);
+        let mut hex = String::new();
+        let mut ascii = String::new();
+        for address in row.clone() {
+            let marker = if address == cpu.pc as usize {
+                '>'
+            } else if address == cpu.i as usize {
+                '@'
+            } else if cpu.written[address] {
+                '*'
+            } else {
+                ' '
+            };
+            let byte = cpu.memory[address];
+            if address < start {
+                hex.push_str("   ");
+                ascii.push(' ');
+            } else {
+                hex.push_str(&format!("{}{:02X}", marker, byte));
+                ascii.push(if byte.is_ascii_graphic() || byte == b' ' { byte as char } else { '.' });
+            }
+        }
+        lines.push(format!("0x{:03X}:{}  {}", address, hex, ascii));
+        address = row.end;
+    }
+    lines.join("\n")
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exports_registers_stack_and_disassembly() {
+        let mut cpu = Cpu::new();
+        cpu.load_game(vec![0x22, 0x04, 0x00, 0x00, 0x60, 0x2A, 0x00, 0xEE, 0x12, 0x08]);
+        cpu.breakpoints.insert(0x206);
+        cpu.emulate_cycle().unwrap();
+        cpu.emulate_cycle().unwrap();
+
+        let state = state_json(&cpu, 2);
+        assert!(state.starts_with("{\"pc\":518,\"i\":0,\"sp\":1,\"dt\":0,\"st\":0,\"display_generation\":0,\"v\":[42,0,"));
+        assert!(state.contains("\"stack\":[512],\"call_stack\":[{\"depth\":1,\"call_site\":512,\"return_address\":514,\"subroutine\":516,\"call\":\"CALL 0x204\"}],"));
+        assert!(state.contains("false],\"key_wait\":null,\"breakpoints\":[518],"));
+        assert!(state.ends_with("\"disassembly\":[{\"address\":518,\"opcode\":238,\"text\":\"RET\",\"breakpoint\":true},{\"address\":520,\"opcode\":4616,\"text\":\"JP 0x208\",\"breakpoint\":false}]}"));
+
+        assert!(memory_page_json(&cpu, 2).unwrap().starts_with("{\"page\":2,\"address\":512,\"bytes\":[34,4,0,0,96,42,0,"));
+        assert_eq!(memory_page_json(&cpu, 16), None);
+    }
+
+    #[test]
+    fn hex_dump_marks_pc_i_and_written_bytes() {
+        let mut cpu = Cpu::new();
+        cpu.load_game(vec![0xA2, 0x08, 0x60, 0x41, 0xF0, 0x55, 0x12, 0x06]);
+        for _ in 0..3 {
+            cpu.emulate_cycle().unwrap();
+        }
+        cpu.write_memory(0x20F, &[0x7E]);
+
+        let dump = hex_dump(&cpu, 0x202, 14);
+        assert_eq!(dump, "0x200:       60 41 F0 55>12 06@41 00 00 00 00 00 00*7E    `A.U..A......~");
+        assert!(memory_page_json(&cpu, 2).unwrap().ends_with("\"written\":[520,527]}"));
+    }
+
+    #[test]
+    fn call_stack_lists_calls_that_havent_returned() {
+        let mut cpu = Cpu::new();
+        // 0x200 CALL 0x206, 0x206 CALL 0x20A, 0x20A RET, then 0x208 CALL 0x20C
+        cpu.load_game(vec![0x22, 0x06, 0x00, 0x00, 0x00, 0x00, 0x22, 0x0A, 0x22, 0x0C, 0x00, 0xEE, 0x12, 0x0C]);
+        for _ in 0..4 {
+            cpu.emulate_cycle().unwrap();
+        }
+
+        let frames = call_stack(&cpu);
+        assert_eq!(frames.len(), 2);
+        assert_eq!((frames[0].depth, frames[0].call_site, frames[0].subroutine), (1, 0x200, Some(0x206)));
+        assert_eq!((frames[1].depth, frames[1].return_address, frames[1].call.as_str()), (2, 0x20A, "CALL 0x20C"));
+    }
+
+}
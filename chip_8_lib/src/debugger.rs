@@ -0,0 +1,366 @@
+use crate::cheat::{self, Cheat, CheatTiming};
+use crate::cheat_search::{CheatSearch, SearchFilter};
+use crate::cpu::{Cpu, CYCLES_PER_FRAME};
+use crate::debug_state::{self, PAGE_SIZE};
+use crate::disassembler;
+use crate::frame_result::FrameResult;
+use crate::key_event::KeyEvent;
+use crate::keypad::Keypad;
+
+pub const HELP: &str = "Commands, all numbers are hexadecimal:
+  s, step [N]          Run N instructions (1 by default), a frame is every 10 of them
+  c, continue [N]      Run up to N frames (600 by default), stopping at breakpoints and errors
+  b, break ADDR        Set or clear a breakpoint
+  r, regs              Show the registers
+  bt                   Show the call stack
+  d, dis [N]           Disassemble N instructions from the program counter (8 by default)
+  x ADDR [LEN]         Show LEN bytes of memory (one page by default). > marks the program
+                       counter, @ marks I and * marks bytes written since the last step or continue
+  w ADDR BYTE...       Write bytes to memory
+  set REG VALUE        Set v0 to vf, i, pc, sp, dt or st
+  key K                Press or release key K
+  press [KEY...]       Hold the CHIP-8 keys that these keyboard keys are mapped to (e.g. q or
+                       ArrowLeft), releasing the rest. With no keys, releases everything
+  search [FILTER]      Start a search for the address of a value, or keep the addresses that
+                       match FILTER since the last search: unchanged, changed, increased,
+                       decreased or equals N (decimal)
+  freeze ADDR VALUE    Write VALUE to ADDR every frame
+  unfreeze ADDR        Stop writing to ADDR
+  cheats               List the cheats loaded with --cheat
+  cheat N              Turn cheat N on or off
+  h, help              Show this help";
+
+// How far `continue` runs when no frame count is given, so a ROM that never hits a breakpoint
+// doesn't hang the debugger
+const CONTINUE_FRAMES: usize = 600;
+
+// A command line debugger. Commands are given one line at a time and return what to print, so
+// the same debugger works on a terminal or anywhere else lines of text can be passed around.
+pub struct Debugger {
+    pub cpu: Cpu,
+    pub search: CheatSearch,
+
+    // Cheats for the ROM being debugged. The `frame` ones are applied here, applying the `load`
+    // ones is up to whoever loaded the ROM.
+    pub cheats: Vec<Cheat>,
+
+    // How the keyboard keys given to `press` map to the keypad
+    pub keypad: Keypad,
+
+    // Instructions stepped through since the last frame began. Stepping goes through the same
+    // frames as `continue`: they begin with the frozen addresses and cheats written, and end with
+    // the timers ticking.
+    steps: usize,
+}
+
+impl Debugger {
+    pub fn new(cpu: Cpu) -> Debugger {
+        let search = CheatSearch::new(&cpu);
+        Debugger { cpu, search, cheats: vec![], keypad: Keypad::default(), steps: 0 }
+    }
+
+    pub fn command(&mut self, line: &str) -> Result<String, String> {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let (command, args) = match words.split_first() {
+            Some((command, args)) => (*command, args),
+            None => return Ok(String::new()),
+        };
+
+        match (command, args) {
+            ("s", _) | ("step", _) => self.step(optional_number(args, 1)?),
+            ("c", _) | ("continue", _) => self.resume(optional_number(args, CONTINUE_FRAMES)?),
+            ("b", [address]) | ("break", [address]) => {
+                let address = number(address)?;
+                if self.cpu.breakpoints.remove(&address) {
+                    Ok(format!("Cleared breakpoint at 0x{:03X}", address))
+                } else {
+                    self.cpu.breakpoints.insert(address);
+                    Ok(format!("Breakpoint at 0x{:03X}", address))
+                }
+            }
+            ("r", []) | ("regs", []) => Ok(self.registers()),
+            ("bt", []) => Ok(self.backtrace()),
+            ("d", _) | ("dis", _) => Ok(self.disassemble(optional_number(args, 8)?)),
+            ("x", [address, rest @ ..]) if rest.len() <= 1 => {
+                let address = number(address)? as usize;
+                if address >= self.cpu.memory.len() {
+                    return Err(format!("0x{:X} is outside memory", address));
+                }
+                let len = optional_number(rest, PAGE_SIZE)?;
+                Ok(debug_state::hex_dump(&self.cpu, address, len))
+            }
+            ("w", [address, bytes @ ..]) if !bytes.is_empty() => {
+                let address = number(address)?;
+                let bytes = bytes
+                    .iter()
+                    .map(|byte| u8::from_str_radix(byte, 16).map_err(|_| format!("{} isn't a byte", byte)))
+                    .collect::<Result<Vec<u8>, String>>()?;
+                self.cpu.write_memory(address, &bytes);
+                Ok(format!("Wrote {} bytes at 0x{:03X}", bytes.len(), address))
+            }
+            ("set", [name, value]) => {
+                self.cpu.set_register(name, number(value)?)?;
+                Ok(self.registers())
+            }
+            ("key", [key]) => {
+                let key = number(key)? as usize;
+                let mut held = self.held_keys();
+                let pressed = held.get_mut(key).ok_or(format!("there is no key {:X}", key))?;
+                *pressed = !*pressed;
+                let message = format!("Key {:X} {}", key, if *pressed { "pressed" } else { "released" });
+                self.hold_keys(held);
+                Ok(message)
+            }
+            ("press", host_keys) => {
+                let keys = host_keys
+                    .iter()
+                    .map(|host_key| self.keypad.key(host_key).ok_or(format!("{} isn't mapped to a key", host_key)))
+                    .collect::<Result<Vec<u8>, String>>()?;
+                let mut held = [false; 16];
+                for key in keys.iter() {
+                    held[*key as usize] = true;
+                }
+                self.hold_keys(held);
+                if keys.is_empty() {
+                    return Ok(String::from("Released all keys"));
+                }
+                let held: Vec<String> = keys.iter().map(|key| format!("{:X}", key)).collect();
+                Ok(format!("Holding keys {}", held.join(" ")))
+            }
+            ("search", []) => {
+                self.search.restart(&self.cpu);
+                Ok(format!("Searching {} addresses", self.search.candidates.len()))
+            }
+            ("search", filter) => {
+                let filter = SearchFilter::parse(&filter.join(" "))?;
+                let count = self.search.filter(&self.cpu, filter);
+                let shown: Vec<String> = self
+                    .search
+                    .candidates
+                    .iter()
+                    .take(16)
+                    .map(|address| format!("0x{:03X}={:02X}", address, self.cpu.memory[*address as usize]))
+                    .collect();
+                Ok(format!("{} addresses left\n{}", count, shown.join(" ")).trim_end().to_string())
+            }
+            ("freeze", [address, value]) => {
+                let (address, value) = (number(address)?, number(value)?);
+                if value > 0xFF {
+                    return Err(format!("{:X} isn't a byte", value));
+                }
+                self.search.freeze(address, value as u8);
+                self.search.apply(&mut self.cpu);
+                Ok(format!("Froze 0x{:03X} at {:02X}", address, value))
+            }
+            ("unfreeze", [address]) => {
+                self.search.unfreeze(number(address)?);
+                Ok(String::new())
+            }
+            ("cheats", []) => {
+                let lines: Vec<String> = self
+                    .cheats
+                    .iter()
+                    .enumerate()
+                    .map(|(idx, cheat)| format!("{} [{}] {}", idx, if cheat.enabled { "on" } else { "off" }, cheat.to_line()))
+                    .collect();
+                Ok(lines.join("\n"))
+            }
+            ("cheat", [idx]) => {
+                let idx: usize = idx.parse().map_err(|_| format!("{} isn't a cheat number", idx))?;
+                let cheat = self.cheats.get_mut(idx).ok_or(format!("there is no cheat {}", idx))?;
+                cheat.enabled = !cheat.enabled;
+                Ok(format!("Cheat {} {}", idx, if cheat.enabled { "on" } else { "off" }))
+            }
+            ("h", []) | ("help", []) => Ok(String::from(HELP)),
+            _ => Err(format!("don't know how to `{}`, try `help`", line.trim())),
+        }
+    }
+
+    fn step(&mut self, count: usize) -> Result<String, String> {
+        self.cpu.clear_written();
+        for _ in 0..count {
+            if self.steps == 0 {
+                self.search.apply(&mut self.cpu);
+                cheat::apply(&self.cheats, CheatTiming::EveryFrame, &mut self.cpu);
+            }
+            if let Err(e) = self.cpu.emulate_cycle() {
+                return Err(format!("Stopped at 0x{:03X}: {}", self.cpu.pc, e.message));
+            }
+            self.steps += 1;
+            if self.steps == CYCLES_PER_FRAME {
+                self.cpu.tick_timers();
+                self.steps = 0;
+            }
+        }
+        Ok(self.disassemble(1))
+    }
+
+    fn resume(&mut self, frames: usize) -> Result<String, String> {
+        self.cpu.clear_written();
+        self.steps = 0;
+        for _ in 0..frames {
+            self.search.apply(&mut self.cpu);
+            cheat::apply(&self.cheats, CheatTiming::EveryFrame, &mut self.cpu);
+            match self.cpu.run_frame() {
+                FrameResult::Completed => {}
+                FrameResult::Breakpoint { pc } => return Ok(format!("Breakpoint at 0x{:03X}\n{}", pc, self.disassemble(1))),
+                FrameResult::WaitingForKey { pressed: Some(key), .. } => {
+                    return Ok(format!("Waiting for key {:X} to be released\n{}", key, self.disassemble(1)))
+                }
+                FrameResult::WaitingForKey { register, .. } => {
+                    return Ok(format!("Waiting for a key to store in V{:X}\n{}", register, self.disassemble(1)))
+                }
+                FrameResult::Error { pc, message, .. } => return Err(format!("Stopped at 0x{:03X}: {}", pc, message)),
+            }
+        }
+        Ok(format!("Ran {} frames\n{}", frames, self.disassemble(1)))
+    }
+
+    // The keys that will be held once the queued key events have been applied
+    fn held_keys(&self) -> [bool; 16] {
+        let mut keys = self.cpu.keys;
+        for event in self.cpu.key_events.iter() {
+            keys[event.key as usize & 0xF] = event.pressed;
+        }
+        keys
+    }
+
+    // Queues events for the keys that change, so they reach the program like real input and
+    // end up in the key log
+    fn hold_keys(&mut self, keys: [bool; 16]) {
+        for (key, (held, pressed)) in self.held_keys().iter().zip(keys.iter()).enumerate() {
+            if held != pressed {
+                let event = if *pressed { KeyEvent::press(key as u8) } else { KeyEvent::release(key as u8) };
+                self.cpu.push_key_event(event);
+            }
+        }
+    }
+
+    fn registers(&self) -> String {
+        let cpu = &self.cpu;
+        let v: Vec<String> = cpu.v.iter().enumerate().map(|(idx, value)| format!("V{:X}={:02X}", idx, value)).collect();
+        format!(
+            "{}\nPC={:03X} I={:03X} SP={:X} DT={:02X} ST={:02X}",
+            v.join(" "),
+            cpu.pc,
+            cpu.i,
+            cpu.sp,
+            cpu.dt,
+            cpu.st
+        )
+    }
+
+    fn backtrace(&self) -> String {
+        let frames = debug_state::call_stack(&self.cpu);
+        if frames.is_empty() {
+            return String::from("Not in a subroutine");
+        }
+        let lines: Vec<String> = frames
+            .iter()
+            .rev()
+            .map(|frame| format!("#{} 0x{:03X}: {}, returns to 0x{:03X}", frame.depth, frame.call_site, frame.call, frame.return_address))
+            .collect();
+        lines.join("\n")
+    }
+
+    // Reads memory as it is now, so bytes written by the program or by `w` show up straight away
+    fn disassemble(&self, count: usize) -> String {
+        let memory = &self.cpu.memory;
+        let lines: Vec<String> = (0..count)
+            .map(|line| (self.cpu.pc as usize + line * 2) % memory.len())
+            .map(|address| {
+                let opcode = (memory[address] as u16) << 8 | memory[(address + 1) % memory.len()] as u16;
+                let marker = if self.cpu.breakpoints.contains(&(address as u16)) { '*' } else { ' ' };
+                format!("{}0x{:03X}: {:04X}  {}", marker, address, opcode, disassembler::disassemble(opcode).trim())
+            })
+            .collect();
+        lines.join("\n")
+    }
+}
+
+fn number(text: &str) -> Result<u16, String> {
+    let digits = text.strip_prefix("0x").unwrap_or(text);
+    u16::from_str_radix(digits, 16).map_err(|_| format!("{} isn't a hexadecimal number", text))
+}
+
+fn optional_number(args: &[&str], default: usize) -> Result<usize, String> {
+    match args {
+        [] => Ok(default),
+        [value] => number(value).map(|value| value as usize),
+        _ => Err(String::from("too many arguments")),
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pokes_memory_and_disassembles_the_new_code() {
+        let mut cpu = Cpu::new();
+        // 0x200 LD V0, 0x01; 0x202 JP 0x200
+        cpu.load_game(vec![0x60, 0x01, 0x12, 0x00]);
+        let mut debugger = Debugger::new(cpu);
+
+        assert_eq!(debugger.command("step 2").unwrap(), " 0x200: 6001  LD V0 1");
+        assert_eq!(debugger.command("w 201 2A").unwrap(), "Wrote 1 bytes at 0x201");
+        assert_eq!(debugger.command("x 200 4").unwrap(), "0x200:>60*2A 12 00  `*..");
+        assert_eq!(debugger.command("s").unwrap(), " 0x202: 1200  JP 0x200");
+        assert_eq!(debugger.cpu.v[0], 0x2A);
+
+        assert!(debugger.command("set vg 1").is_err());
+        debugger.command("set v3 ff").unwrap();
+        assert_eq!(debugger.cpu.v[3], 0xFF);
+    }
+
+    #[test]
+    fn continue_stops_at_breakpoints() {
+        let mut cpu = Cpu::new();
+        // 0x200 CALL 0x204; 0x202 JP 0x202; 0x204 RET
+        cpu.load_game(vec![0x22, 0x04, 0x12, 0x02, 0x00, 0xEE]);
+        let mut debugger = Debugger::new(cpu);
+
+        assert_eq!(debugger.command("b 204").unwrap(), "Breakpoint at 0x204");
+        assert_eq!(debugger.command("c").unwrap(), "Breakpoint at 0x204\n*0x204: 00EE  RET");
+        assert_eq!(debugger.command("bt").unwrap(), "#1 0x200: CALL 0x204, returns to 0x202");
+        assert_eq!(debugger.command("c 2").unwrap(), "Ran 2 frames\n 0x202: 1202  JP 0x202");
+        assert!(debugger.command("launch").is_err());
+    }
+
+    #[test]
+    fn keys_are_queued_and_logged() {
+        let mut cpu = Cpu::new();
+        cpu.load_game(vec![0x12, 0x00]);
+        cpu.key_log = Some(vec![]);
+        let mut debugger = Debugger::new(cpu);
+
+        assert_eq!(debugger.command("key 5").unwrap(), "Key 5 pressed");
+        assert_eq!(debugger.command("key 5").unwrap(), "Key 5 released");
+        assert_eq!(debugger.command("press 1 q").unwrap(), "Holding keys 1 4");
+        assert!(!debugger.cpu.keys[1]);
+        debugger.command("s").unwrap();
+        debugger.command("s").unwrap();
+        assert_eq!((debugger.cpu.keys[1], debugger.cpu.keys[4], debugger.cpu.keys[5]), (true, true, false));
+        assert_eq!(debugger.cpu.key_log.as_ref().unwrap().len(), 4);
+    }
+
+    #[test]
+    fn steps_freeze_and_tick_once_a_frame() {
+        let mut cpu = Cpu::new();
+        // 0x200 LD I, 0x300; 0x202 LD V0, 7; 0x204 LD [I], V0; 0x206 JP 0x202
+        cpu.load_game(vec![0xA3, 0x00, 0x60, 0x07, 0xF0, 0x55, 0x12, 0x02]);
+        let mut debugger = Debugger::new(cpu);
+        debugger.command("set dt 3").unwrap();
+        debugger.command("freeze 300 5").unwrap();
+
+        // The program's write sticks until the next frame begins
+        debugger.command("s 4").unwrap();
+        assert_eq!((debugger.cpu.memory[0x300], debugger.cpu.dt), (7, 3));
+        debugger.command("s 6").unwrap();
+        assert_eq!((debugger.cpu.memory[0x300], debugger.cpu.dt), (7, 2));
+        debugger.command("s").unwrap();
+        assert_eq!(debugger.cpu.memory[0x300], 5);
+    }
+
+}
@@ -0,0 +1,39 @@
+// An area of the display, in pixels
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DirtyRect {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+}
+
+impl DirtyRect {
+    pub fn pixel(x: usize, y: usize) -> DirtyRect {
+        DirtyRect { x, y, width: 1, height: 1 }
+    }
+
+    // The smallest rectangle covering both
+    pub fn union(&self, other: &DirtyRect) -> DirtyRect {
+        let x = self.x.min(other.x);
+        let y = self.y.min(other.y);
+        DirtyRect {
+            x,
+            y,
+            width: (self.x + self.width).max(other.x + other.width) - x,
+            height: (self.y + self.height).max(other.y + other.height) - y,
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn union_covers_both() {
+        let rect = DirtyRect::pixel(10, 2).union(&DirtyRect { x: 4, y: 5, width: 3, height: 1 });
+        assert_eq!(rect, DirtyRect { x: 4, y: 2, width: 7, height: 4 });
+    }
+
+}
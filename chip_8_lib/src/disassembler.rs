@@ -249,3 +249,86 @@ pub fn disassemble(opcode: u16) -> String {
         }
     }
 }
+
+
+// Returns the opcode pattern (as written in Cowgod's reference) an opcode belongs to,
+// for example 0x8124 is "8xy4". SUPER-CHIP and XO-CHIP extensions are included so ROMs
+// written for those platforms can be recognised, even though the Cpu can't run them.
+pub fn opcode_pattern(opcode: u16) -> &'static str {
+    let x = (opcode & 0x0F00) >> 8;
+    let n = opcode & 0x000F;
+    let kk = opcode & 0x00FF;
+    match opcode & 0xF000 {
+        0x0000 => match opcode {
+            0x00E0 => "00E0",
+            0x00EE => "00EE",
+            0x00C0..=0x00CF => "00Cn",
+            0x00D0..=0x00DF => "00Dn",
+            0x00FB => "00FB",
+            0x00FC => "00FC",
+            0x00FD => "00FD",
+            0x00FE => "00FE",
+            0x00FF => "00FF",
+            _ => "0nnn",
+        },
+        0x1000 => "1nnn",
+        0x2000 => "2nnn",
+        0x3000 => "3xkk",
+        0x4000 => "4xkk",
+        0x5000 => match n {
+            0x0 => "5xy0",
+            0x2 => "5xy2",
+            0x3 => "5xy3",
+            _ => "5xy?",
+        },
+        0x6000 => "6xkk",
+        0x7000 => "7xkk",
+        0x8000 => match n {
+            0x0 => "8xy0",
+            0x1 => "8xy1",
+            0x2 => "8xy2",
+            0x3 => "8xy3",
+            0x4 => "8xy4",
+            0x5 => "8xy5",
+            0x6 => "8xy6",
+            0x7 => "8xy7",
+            0xE => "8xyE",
+            _ => "8xy?",
+        },
+        0x9000 => match n {
+            0x0 => "9xy0",
+            _ => "9xy?",
+        },
+        0xA000 => "Annn",
+        0xB000 => "Bnnn",
+        0xC000 => "Cxkk",
+        0xD000 => match n {
+            0x0 => "Dxy0",
+            _ => "Dxyn",
+        },
+        0xE000 => match kk {
+            0x9E => "Ex9E",
+            0xA1 => "ExA1",
+            _ => "Ex??",
+        },
+        _ => match kk {
+            0x00 if x == 0 => "F000 nnnn",
+            0x01 => "Fn01",
+            0x02 if x == 0 => "F002",
+            0x07 => "Fx07",
+            0x0A => "Fx0A",
+            0x15 => "Fx15",
+            0x18 => "Fx18",
+            0x1E => "Fx1E",
+            0x29 => "Fx29",
+            0x30 => "Fx30",
+            0x33 => "Fx33",
+            0x3A => "Fx3A",
+            0x55 => "Fx55",
+            0x65 => "Fx65",
+            0x75 => "Fx75",
+            0x85 => "Fx85",
+            _ => "Fx??",
+        },
+    }
+}
@@ -0,0 +1,39 @@
+use crate::json;
+
+// How a call to Cpu::run_frame() ended
+#[derive(Clone, Debug, PartialEq)]
+pub enum FrameResult {
+    // Every cycle of the frame ran
+    Completed,
+
+    // The program counter reached a breakpoint, the instruction there hasn't run yet
+    Breakpoint { pc: u16 },
+
+    // Fx0A is waiting for a key to be pressed, the rest of the frame was skipped. When the
+    // wait_for_release quirk is set, pressed is the key it's waiting to be released.
+    WaitingForKey { pc: u16, register: usize, pressed: Option<u8> },
+
+    // The instruction at pc failed
+    Error { pc: u16, opcode: u16, message: String },
+}
+
+impl FrameResult {
+    pub fn to_json(&self) -> String {
+        match self {
+            FrameResult::Completed => String::from("{\"status\":\"ok\"}"),
+            FrameResult::Breakpoint { pc } => format!("{{\"status\":\"breakpoint\",\"pc\":{}}}", pc),
+            FrameResult::WaitingForKey { pc, register, pressed } => format!(
+                "{{\"status\":\"waiting_for_key\",\"pc\":{},\"register\":{},\"pressed\":{}}}",
+                pc,
+                register,
+                pressed.map(|key| key.to_string()).unwrap_or_else(|| String::from("null"))
+            ),
+            FrameResult::Error { pc, opcode, message } => format!(
+                "{{\"status\":\"error\",\"pc\":{},\"opcode\":{},\"message\":{}}}",
+                pc,
+                opcode,
+                json::string(message)
+            ),
+        }
+    }
+}
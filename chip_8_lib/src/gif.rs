@@ -0,0 +1,214 @@
+use std::collections::HashMap;
+
+// LZW codes can't be longer than 12 bits
+const MAX_CODES: u16 = 4096;
+
+// Encodes an animated GIF that loops forever. Each frame is a full RGBA image with its own
+// colour table, so blended colours from the persistence filter survive. Frames with more
+// than 256 colours lose the low bits of each channel until they fit.
+#[derive(Clone, Debug)]
+pub struct GifEncoder {
+    pub width: usize,
+    pub height: usize,
+    bytes: Vec<u8>,
+}
+
+impl GifEncoder {
+    pub fn new(width: usize, height: usize) -> GifEncoder {
+        let mut bytes = b"GIF89a".to_vec();
+        bytes.extend_from_slice(&(width as u16).to_le_bytes());
+        bytes.extend_from_slice(&(height as u16).to_le_bytes());
+        // No global colour table, background colour 0 and square pixels
+        bytes.extend_from_slice(&[0, 0, 0]);
+        // Loop forever
+        bytes.extend_from_slice(&[0x21, 0xFF, 11]);
+        bytes.extend_from_slice(b"NETSCAPE2.0");
+        bytes.extend_from_slice(&[3, 1, 0, 0, 0]);
+
+        GifEncoder { width, height, bytes }
+    }
+
+    // Adds a frame shown for `delay` hundredths of a second
+    pub fn add_frame(&mut self, rgba: &[u8], delay: u16) {
+        assert_eq!(rgba.len(), self.width * self.height * 4, "frame doesn't match the image size");
+        let (colors, indexes) = index_colors(rgba);
+        let mut bits = 1;
+        while (1 << bits) < colors.len() {
+            bits += 1;
+        }
+
+        self.bytes.extend_from_slice(&[0x21, 0xF9, 4, 0]);
+        self.bytes.extend_from_slice(&delay.to_le_bytes());
+        self.bytes.extend_from_slice(&[0, 0]);
+
+        self.bytes.extend_from_slice(&[0x2C, 0, 0, 0, 0]);
+        self.bytes.extend_from_slice(&(self.width as u16).to_le_bytes());
+        self.bytes.extend_from_slice(&(self.height as u16).to_le_bytes());
+        // A local colour table with 2^bits entries
+        self.bytes.push(0x80 | (bits - 1));
+        for idx in 0..1 << bits {
+            self.bytes.extend_from_slice(&colors.get(idx).cloned().unwrap_or([0, 0, 0]));
+        }
+
+        let min_code_size = bits.max(2);
+        self.bytes.push(min_code_size);
+        for block in lzw(&indexes, min_code_size).chunks(255) {
+            self.bytes.push(block.len() as u8);
+            self.bytes.extend_from_slice(block);
+        }
+        self.bytes.push(0);
+    }
+
+    pub fn finish(mut self) -> Vec<u8> {
+        self.bytes.push(0x3B);
+        self.bytes
+    }
+}
+
+// The colours in an RGBA image, at most 256 of them, and the index of each pixel's colour
+fn index_colors(rgba: &[u8]) -> (Vec<[u8; 3]>, Vec<u8>) {
+    let mut mask = 0xFF;
+    loop {
+        let mut colors = vec![];
+        let mut lookup: HashMap<[u8; 3], u8> = HashMap::new();
+        let mut indexes = Vec::with_capacity(rgba.len() / 4);
+        for pixel in rgba.chunks(4) {
+            let color = [pixel[0] & mask, pixel[1] & mask, pixel[2] & mask];
+            let index = match lookup.get(&color) {
+                Some(index) => *index,
+                None if colors.len() == 256 => break,
+                None => {
+                    lookup.insert(color, colors.len() as u8);
+                    colors.push(color);
+                    (colors.len() - 1) as u8
+                }
+            };
+            indexes.push(index);
+        }
+        if indexes.len() == rgba.len() / 4 {
+            return (colors, indexes);
+        }
+        mask <<= 1;
+    }
+}
+
+// Compresses indexes the way GIF wants, with variable length codes packed least significant
+// bit first
+fn lzw(indexes: &[u8], min_code_size: u8) -> Vec<u8> {
+    let clear = 1u16 << min_code_size;
+    let end = clear + 1;
+    let mut output = BitWriter::default();
+    let mut code_size = min_code_size + 1;
+    let mut next_code = end + 1;
+    let mut table: HashMap<(u16, u8), u16> = HashMap::new();
+
+    output.write(clear, code_size);
+    let mut current: Option<u16> = None;
+    for index in indexes {
+        let prefix = match current {
+            None => {
+                current = Some(*index as u16);
+                continue;
+            }
+            Some(prefix) => prefix,
+        };
+        if let Some(code) = table.get(&(prefix, *index)) {
+            current = Some(*code);
+            continue;
+        }
+
+        output.write(prefix, code_size);
+        if next_code < MAX_CODES {
+            table.insert((prefix, *index), next_code);
+            next_code += 1;
+            if next_code > 1 << code_size && code_size < 12 {
+                code_size += 1;
+            }
+        } else {
+            output.write(clear, code_size);
+            table.clear();
+            code_size = min_code_size + 1;
+            next_code = end + 1;
+        }
+        current = Some(*index as u16);
+    }
+    if let Some(code) = current {
+        output.write(code, code_size);
+    }
+    output.write(end, code_size);
+    output.finish()
+}
+
+#[derive(Default)]
+struct BitWriter {
+    bytes: Vec<u8>,
+    buffer: u32,
+    bits: u8,
+}
+
+impl BitWriter {
+    fn write(&mut self, code: u16, size: u8) {
+        self.buffer |= (code as u32) << self.bits;
+        self.bits += size;
+        while self.bits >= 8 {
+            self.bytes.push(self.buffer as u8);
+            self.buffer >>= 8;
+            self.bits -= 8;
+        }
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        if self.bits > 0 {
+            self.bytes.push(self.buffer as u8);
+        }
+        self.bytes
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn drops_low_bits_when_there_are_too_many_colours() {
+        let rgba: Vec<u8> = (0..300).flat_map(|idx| vec![(idx % 256) as u8, (idx / 256) as u8, 0, 255]).collect();
+        let (colors, indexes) = index_colors(&rgba);
+        assert_eq!(colors.len(), 128);
+        assert_eq!((colors[1], indexes[2], indexes[3]), ([2, 0, 0], 1, 1));
+    }
+
+    #[test]
+    fn compresses_like_the_gif_spec_example() {
+        // The 10x10 red, blue and white example from the GIF spec walkthrough
+        let rows = [
+            [1, 1, 1, 1, 1, 2, 2, 2, 2, 2],
+            [1, 1, 1, 1, 1, 2, 2, 2, 2, 2],
+            [1, 1, 1, 1, 1, 2, 2, 2, 2, 2],
+            [1, 1, 1, 0, 0, 0, 0, 2, 2, 2],
+            [1, 1, 1, 0, 0, 0, 0, 2, 2, 2],
+            [2, 2, 2, 0, 0, 0, 0, 1, 1, 1],
+            [2, 2, 2, 0, 0, 0, 0, 1, 1, 1],
+            [2, 2, 2, 2, 2, 1, 1, 1, 1, 1],
+            [2, 2, 2, 2, 2, 1, 1, 1, 1, 1],
+            [2, 2, 2, 2, 2, 1, 1, 1, 1, 1],
+        ];
+        let indexes: Vec<u8> = rows.iter().flat_map(|row| row.iter().cloned()).collect();
+        let expected = [
+            0x8C, 0x2D, 0x99, 0x87, 0x2A, 0x1C, 0xDC, 0x33, 0xA0, 0x02, 0x75, 0xEC, 0x95, 0xFA, 0xA8, 0xDE,
+            0x60, 0x8C, 0x04, 0x91, 0x4C, 0x01,
+        ];
+        assert_eq!(lzw(&indexes, 2), expected);
+
+        let colors = [[255, 255, 255], [255, 0, 0], [0, 0, 255]];
+        let rgba: Vec<u8> = indexes.iter().flat_map(|idx| colors[*idx as usize].iter().cloned().chain(Some(255))).collect();
+        let mut gif = GifEncoder::new(10, 10);
+        gif.add_frame(&rgba, 10);
+        let bytes = gif.finish();
+        assert_eq!(&bytes[..6], b"GIF89a");
+        // The colours are in the order they first appear
+        assert_eq!(bytes[49..62], [0x80 | 1, 255, 0, 0, 0, 0, 255, 255, 255, 255, 0, 0, 0]);
+        assert_eq!(bytes.last(), Some(&0x3B));
+    }
+
+}
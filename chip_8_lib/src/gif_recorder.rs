@@ -0,0 +1,97 @@
+use crate::gif::GifEncoder;
+use crate::renderer::Renderer;
+
+// The shortest delay to give a frame, in hundredths of a second. Browsers show anything
+// quicker than this much slower, so frames in between are dropped instead.
+const MIN_DELAY: u64 = 2;
+
+// Records the display at 60 frames a second as an animated GIF, only adding a frame when the
+// display has changed
+#[derive(Clone, Debug)]
+pub struct GifRecorder {
+    pub renderer: Renderer,
+    encoder: GifEncoder,
+
+    // The latest image, waiting to find out how long it's shown for, and the frame it was
+    // first shown on
+    pending: Option<(Vec<u8>, u64)>,
+
+    frames: u64,
+}
+
+impl GifRecorder {
+    pub fn new(renderer: Renderer) -> GifRecorder {
+        let encoder = GifEncoder::new(renderer.width(), renderer.height());
+        GifRecorder { renderer, encoder, pending: None, frames: 0 }
+    }
+
+    // Call once a frame with the display. The renderer's persistence filter is applied too.
+    pub fn record_frame(&mut self, display: &[u8]) {
+        let frame = self.frames;
+        self.frames += 1;
+        self.renderer.update(display);
+        let image = self.renderer.render();
+
+        match self.pending.take() {
+            Some((pending, shown_at)) if pending == image => self.pending = Some((pending, shown_at)),
+            Some((_, shown_at)) if centiseconds(frame) - centiseconds(shown_at) < MIN_DELAY => {
+                self.pending = Some((image, shown_at));
+            }
+            Some((pending, shown_at)) => {
+                self.add(&pending, shown_at, frame);
+                self.pending = Some((image, frame));
+            }
+            None => self.pending = Some((image, frame)),
+        }
+    }
+
+    pub fn finish(mut self) -> Vec<u8> {
+        if let Some((pending, shown_at)) = self.pending.take() {
+            let frames = self.frames;
+            self.add(&pending, shown_at, frames);
+        }
+        self.encoder.finish()
+    }
+
+    // Delays are rounded from the start of the recording so they don't drift from 60 Hz
+    fn add(&mut self, image: &[u8], from: u64, to: u64) {
+        let delay = (centiseconds(to) - centiseconds(from)).max(MIN_DELAY);
+        self.encoder.add_frame(image, delay.min(u16::MAX as u64) as u16);
+    }
+}
+
+fn centiseconds(frame: u64) -> u64 {
+    (frame * 100 + 30) / 60
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_adds_frames_when_the_display_changes() {
+        let mut recorder = GifRecorder::new(Renderer::default());
+        let blank = [0; 64 * 32];
+        let mut lit = blank;
+        lit[0] = 1;
+        recorder.record_frame(&blank);
+        // Only shown for 1/60th of a second, which rounds down to nothing at that point
+        recorder.record_frame(&lit);
+        for _ in 0..59 {
+            recorder.record_frame(&blank);
+        }
+        for _ in 0..30 {
+            recorder.record_frame(&lit);
+        }
+        let gif = recorder.finish();
+
+        // Every frame starts with a graphic control extension holding its delay
+        let delays: Vec<u16> = (0..gif.len() - 6)
+            .filter(|offset| gif[*offset..*offset + 3] == [0x21, 0xF9, 4])
+            .map(|offset| u16::from_le_bytes([gif[offset + 4], gif[offset + 5]]))
+            .collect();
+        assert_eq!(delays, vec![2, 100, 50]);
+    }
+
+}
@@ -0,0 +1,207 @@
+use std::fs;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::Path;
+
+use crate::checksum;
+use crate::cpu::Cpu;
+use crate::frame_result::FrameResult;
+use crate::movie::{mask_to_keys, Movie};
+use crate::quirks::Quirks;
+
+// How long ROMs without a movie of their own are run for
+pub const FRAMES: usize = 600;
+
+// Seed for Cxkk used by the default movie
+pub const SEED: u64 = 1;
+
+const WIDTH: usize = 64;
+const HEIGHT: usize = 32;
+
+// The screen at the end of a golden run. Goldens are saved as plain PBM images, with the
+// display hash and any error that stopped the run in comments:
+//
+//     P1
+//     # crc32 7F3A9C01
+//     64 32
+//     0000000000000000000000000000000000000000000000000000000000000000
+//     ...
+#[derive(Clone, Debug, PartialEq)]
+pub struct Golden {
+    pub display: Vec<u8>,
+    pub display_hash: u32,
+    pub error: Option<String>,
+}
+
+// Holds each key in turn for half a second, then lets go for half a second
+pub fn default_movie(rom: &[u8]) -> Movie {
+    let mut movie = Movie::new(rom, SEED, Quirks::DEFAULT);
+    for frame in 0..FRAMES {
+        let mask = if frame % 60 < 30 { 1 << ((frame / 60) % 16) } else { 0 };
+        movie.record_frame(&mask_to_keys(mask));
+    }
+    movie
+}
+
+// Plays the movie on a fresh machine, stopping early if the ROM hits an error
+pub fn run(rom: &[u8], movie: &Movie) -> Golden {
+    let mut cpu = Cpu::new();
+    cpu.load_game(rom.to_vec());
+    movie.start(&mut cpu);
+
+    let played = panic::catch_unwind(AssertUnwindSafe(|| {
+        for mask in movie.frames.iter() {
+            if !movie.has_events() {
+                cpu.keys = mask_to_keys(*mask);
+            }
+            if let FrameResult::Error { message, .. } = cpu.run_frame() {
+                return Some(message);
+            }
+        }
+        None
+    }));
+    let error = played.unwrap_or_else(|_| Some(String::from("emulator panicked")));
+
+    Golden {
+        display: cpu.display.to_vec(),
+        display_hash: checksum::crc32(&cpu.display),
+        error,
+    }
+}
+
+impl Golden {
+    pub fn to_pbm(&self) -> String {
+        let mut lines = vec![String::from("P1"), format!("# crc32 {:08X}", self.display_hash)];
+        if let Some(error) = &self.error {
+            lines.push(format!("# error {}", error));
+        }
+        lines.push(format!("{} {}", WIDTH, HEIGHT));
+        for row in self.display.chunks(WIDTH) {
+            lines.push(row.iter().map(|pixel| if *pixel == 1 { '1' } else { '0' }).collect());
+        }
+        lines.join("\n") + "\n"
+    }
+
+    pub fn parse_pbm(text: &str) -> Result<Golden, String> {
+        let mut display_hash = None;
+        let mut error = None;
+        let mut tokens = vec![];
+        for line in text.lines() {
+            let (data, comment) = match line.find('#') {
+                Some(idx) => (&line[..idx], Some(line[idx + 1..].trim())),
+                None => (line, None),
+            };
+            match comment {
+                Some(comment) if comment.starts_with("crc32 ") => {
+                    let hash = u32::from_str_radix(comment[6..].trim(), 16).map_err(|_| "bad crc32 comment")?;
+                    display_hash = Some(hash);
+                }
+                Some(comment) if comment.starts_with("error ") => error = Some(comment[6..].to_string()),
+                _ => {}
+            }
+            tokens.extend(data.split_whitespace());
+        }
+
+        if tokens.len() < 3 || tokens[0] != "P1" {
+            return Err(String::from("not a plain PBM image"));
+        }
+        if tokens[1] != WIDTH.to_string() || tokens[2] != HEIGHT.to_string() {
+            return Err(format!("image is {}x{}, expected {}x{}", tokens[1], tokens[2], WIDTH, HEIGHT));
+        }
+        let mut display = vec![];
+        for pixel in tokens[3..].iter().flat_map(|token| token.chars()) {
+            match pixel {
+                '0' => display.push(0),
+                '1' => display.push(1),
+                _ => return Err(format!("unexpected `{}` in image", pixel)),
+            }
+        }
+        if display.len() != WIDTH * HEIGHT {
+            return Err(format!("image has {} pixels, expected {}", display.len(), WIDTH * HEIGHT));
+        }
+
+        let display_hash = display_hash.unwrap_or_else(|| checksum::crc32(&display));
+        Ok(Golden { display, display_hash, error })
+    }
+}
+
+// Runs every ROM in roms_dir and compares the result against its golden in golden_dir. A ROM
+// is played with the movie golden_dir/<ROM>.c8m if there is one, otherwise the default movie.
+//
+// Returns a description of every ROM that doesn't match. When blessing, the goldens of those
+// ROMs are rewritten instead and the descriptions say what changed.
+pub fn check(roms_dir: &Path, golden_dir: &Path, bless: bool) -> Result<Vec<String>, String> {
+    let mut entries: Vec<_> = fs::read_dir(roms_dir)
+        .map_err(|e| format!("could not read {}: {}", roms_dir.display(), e))?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.is_file())
+        .collect();
+    entries.sort();
+
+    let mut mismatches = vec![];
+    for path in entries {
+        let rom_name = path.file_name().unwrap().to_string_lossy().to_string();
+        let rom = fs::read(&path).map_err(|e| format!("could not read {}: {}", path.display(), e))?;
+
+        let movie_path = golden_dir.join(format!("{}.c8m", rom_name));
+        let movie = if movie_path.exists() {
+            let text = fs::read_to_string(&movie_path).map_err(|e| format!("{}: {}", movie_path.display(), e))?;
+            Movie::parse(&text).map_err(|e| format!("{}: {}", movie_path.display(), e))?
+        } else {
+            default_movie(&rom)
+        };
+        let actual = run(&rom, &movie);
+
+        let golden_path = golden_dir.join(format!("{}.pbm", rom_name));
+        let expected = match fs::read_to_string(&golden_path) {
+            Ok(text) => Some(Golden::parse_pbm(&text).map_err(|e| format!("{}: {}", golden_path.display(), e))?),
+            Err(_) => None,
+        };
+
+        let mismatch = match &expected {
+            None => format!("{}: no golden image", rom_name),
+            Some(expected) if expected.display_hash != actual.display_hash || expected.error != actual.error => {
+                describe(&rom_name, expected, &actual)
+            }
+            Some(_) => continue,
+        };
+        if bless {
+            fs::write(&golden_path, actual.to_pbm()).map_err(|e| format!("{}: {}", golden_path.display(), e))?;
+        }
+        mismatches.push(mismatch);
+    }
+    Ok(mismatches)
+}
+
+// Shows the golden and actual screens side by side
+fn describe(rom_name: &str, expected: &Golden, actual: &Golden) -> String {
+    let outcome = |golden: &Golden| match &golden.error {
+        Some(error) => format!("{:08X}, stopped with `{}`", golden.display_hash, error),
+        None => format!("{:08X}", golden.display_hash),
+    };
+    let mut lines = vec![format!("{}: expected {}, got {}", rom_name, outcome(expected), outcome(actual))];
+    let rows = expected.display.chunks(WIDTH).zip(actual.display.chunks(WIDTH));
+    for (expected_row, actual_row) in rows {
+        let render = |row: &[u8]| row.iter().map(|pixel| if *pixel == 1 { '#' } else { '.' }).collect::<String>();
+        lines.push(format!("{}  {}", render(expected_row), render(actual_row)));
+    }
+    lines.join("\n")
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pbm_round_trips() {
+        let rom = vec![0xA0, 0x00, 0xD0, 0x05, 0x00, 0x00];
+        let golden = run(&rom, &default_movie(&rom));
+        assert_eq!(golden.error, Some(String::from("0 opcode not handled")));
+        assert_eq!(golden.display[..5], [1, 1, 1, 1, 0]);
+
+        let pbm = golden.to_pbm();
+        assert!(pbm.starts_with(&format!("P1\n# crc32 {:08X}\n# error 0 opcode not handled\n64 32\n11110", golden.display_hash)));
+        assert_eq!(Golden::parse_pbm(&pbm).unwrap(), golden);
+    }
+
+}
@@ -0,0 +1,133 @@
+// An opcode decoded into the instruction it represents, with its operands extracted.
+// Register operands (x and y) are indexes into Cpu::v, kept to a byte so the instruction cache
+// stays small.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Instruction {
+    // 00E0 - CLS
+    Cls,
+    // 00EE - RET
+    Ret,
+    // 1nnn - JP addr
+    Jp(u16),
+    // 2nnn - CALL addr
+    Call(u16),
+    // 3xkk - SE Vx, byte
+    SeByte(u8, u8),
+    // 4xkk - SNE Vx, byte
+    SneByte(u8, u8),
+    // 5xy0 - SE Vx, Vy
+    SeReg(u8, u8),
+    // 6xkk - LD Vx, byte
+    LdByte(u8, u8),
+    // 7xkk - ADD Vx, byte
+    AddByte(u8, u8),
+    // 8xy0 - LD Vx, Vy
+    LdReg(u8, u8),
+    // 8xy1 - OR Vx, Vy
+    Or(u8, u8),
+    // 8xy2 - AND Vx, Vy
+    And(u8, u8),
+    // 8xy3 - XOR Vx, Vy
+    Xor(u8, u8),
+    // 8xy4 - ADD Vx, Vy
+    AddReg(u8, u8),
+    // 8xy5 - SUB Vx, Vy
+    Sub(u8, u8),
+    // 8xy6 - SHR Vx {, Vy}
+    Shr(u8, u8),
+    // 8xy7 - SUBN Vx, Vy
+    Subn(u8, u8),
+    // 8xyE - SHL Vx {, Vy}
+    Shl(u8, u8),
+    // 9xy0 - SNE Vx, Vy
+    SneReg(u8, u8),
+    // Annn - LD I, addr
+    LdI(u16),
+    // Bnnn - JP V0, addr
+    JpV0(u16),
+    // Cxkk - RND Vx, byte
+    Rnd(u8, u8),
+    // Dxyn - DRW Vx, Vy, nibble
+    Drw(u8, u8, u8),
+    // Ex9E - SKP Vx
+    Skp(u8),
+    // ExA1 - SKNP Vx
+    Sknp(u8),
+    // Fx07 - LD Vx, DT
+    LdVxDt(u8),
+    // Fx0A - LD Vx, K
+    LdVxK(u8),
+    // Fx15 - LD DT, Vx
+    LdDtVx(u8),
+    // Fx18 - LD ST, Vx
+    LdStVx(u8),
+    // Fx1E - ADD I, Vx
+    AddIVx(u8),
+    // Fx29 - LD F, Vx
+    LdFVx(u8),
+    // Fx33 - LD B, Vx
+    LdBVx(u8),
+    // Fx55 - LD [I], Vx
+    LdIVx(u8),
+    // Fx65 - LD Vx, [I]
+    LdVxI(u8),
+    // Anything the Cpu doesn't implement
+    Unknown(u16),
+}
+
+impl Instruction {
+    pub fn decode(opcode: u16) -> Instruction {
+        let x = ((opcode & 0x0F00) >> 8) as u8;
+        let y = ((opcode & 0x00F0) >> 4) as u8;
+        let n = (opcode & 0x000F) as u8;
+        let kk = (opcode & 0x00FF) as u8;
+        let nnn = opcode & 0x0FFF;
+
+        match opcode {
+            0x00E0 => Instruction::Cls,
+            0x00EE => Instruction::Ret,
+            0x1000..=0x1FFF => Instruction::Jp(nnn),
+            0x2000..=0x2FFF => Instruction::Call(nnn),
+            0x3000..=0x3FFF => Instruction::SeByte(x, kk),
+            0x4000..=0x4FFF => Instruction::SneByte(x, kk),
+            0x5000..=0x5FFF if n == 0 => Instruction::SeReg(x, y),
+            0x6000..=0x6FFF => Instruction::LdByte(x, kk),
+            0x7000..=0x7FFF => Instruction::AddByte(x, kk),
+            0x8000..=0x8FFF => match n {
+                0x0 => Instruction::LdReg(x, y),
+                0x1 => Instruction::Or(x, y),
+                0x2 => Instruction::And(x, y),
+                0x3 => Instruction::Xor(x, y),
+                0x4 => Instruction::AddReg(x, y),
+                0x5 => Instruction::Sub(x, y),
+                0x6 => Instruction::Shr(x, y),
+                0x7 => Instruction::Subn(x, y),
+                0xE => Instruction::Shl(x, y),
+                _ => Instruction::Unknown(opcode),
+            },
+            0x9000..=0x9FFF if n == 0 => Instruction::SneReg(x, y),
+            0xA000..=0xAFFF => Instruction::LdI(nnn),
+            0xB000..=0xBFFF => Instruction::JpV0(nnn),
+            0xC000..=0xCFFF => Instruction::Rnd(x, kk),
+            0xD000..=0xDFFF => Instruction::Drw(x, y, n),
+            0xE000..=0xEFFF => match kk {
+                0x9E => Instruction::Skp(x),
+                0xA1 => Instruction::Sknp(x),
+                _ => Instruction::Unknown(opcode),
+            },
+            0xF000..=0xFFFF => match kk {
+                0x07 => Instruction::LdVxDt(x),
+                0x0A => Instruction::LdVxK(x),
+                0x15 => Instruction::LdDtVx(x),
+                0x18 => Instruction::LdStVx(x),
+                0x1E => Instruction::AddIVx(x),
+                0x29 => Instruction::LdFVx(x),
+                0x33 => Instruction::LdBVx(x),
+                0x55 => Instruction::LdIVx(x),
+                0x65 => Instruction::LdVxI(x),
+                _ => Instruction::Unknown(opcode),
+            },
+            _ => Instruction::Unknown(opcode),
+        }
+    }
+}
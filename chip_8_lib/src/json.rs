@@ -0,0 +1,45 @@
+// Just enough JSON writing for the reports and exports, so we don't need a serialization dependency.
+
+// Quotes and escapes a string for use as a JSON string value.
+pub fn string(value: &str) -> String {
+    let mut output = String::with_capacity(value.len() + 2);
+    output.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => output.push_str("\\\""),
+            '\\' => output.push_str("\\\\"),
+            '\n' => output.push_str("\\n"),
+            '\r' => output.push_str("\\r"),
+            '\t' => output.push_str("\\t"),
+            c if (c as u32) < 0x20 => output.push_str(&format!("\\u{:04x}", c as u32)),
+            c => output.push(c),
+        }
+    }
+    output.push('"');
+    output
+}
+
+// A JSON array of values that are already JSON
+pub fn array<T: ToString>(values: impl IntoIterator<Item = T>) -> String {
+    let values: Vec<String> = values.into_iter().map(|value| value.to_string()).collect();
+    format!("[{}]", values.join(","))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn escapes_strings() {
+        assert_eq!(string("LD V1, DT"), "\"LD V1, DT\"");
+        assert_eq!(string("a\"b\\c\n\u{1}"), "\"a\\\"b\\\\c\\n\\u0001\"");
+    }
+
+    #[test]
+    fn writes_arrays() {
+        assert_eq!(array([1, 2, 3]), "[1,2,3]");
+        assert_eq!(array(vec![string("a")]), "[\"a\"]");
+    }
+
+}
@@ -0,0 +1,25 @@
+// A key being pressed or released. Frontends queue these with Cpu::push_key_event() rather
+// than setting Cpu::keys, so a tap that's over before the next instruction still gets seen.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct KeyEvent {
+    pub key: u8,
+    pub pressed: bool,
+
+    // The instruction (counting from 0 since the Cpu was created) to apply the event before, or
+    // None to apply it as soon as possible
+    pub cycle: Option<u64>,
+}
+
+impl KeyEvent {
+    pub fn press(key: u8) -> KeyEvent {
+        KeyEvent { key: key & 0xF, pressed: true, cycle: None }
+    }
+
+    pub fn release(key: u8) -> KeyEvent {
+        KeyEvent { key: key & 0xF, pressed: false, cycle: None }
+    }
+
+    pub fn at(self, cycle: u64) -> KeyEvent {
+        KeyEvent { cycle: Some(cycle), ..self }
+    }
+}
@@ -0,0 +1,71 @@
+// The progress of an Fx0A instruction waiting for a key. Only a key pressed while waiting
+// counts, so a key that's still held from before doesn't get read again and again.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct KeyWait {
+    // Address of the Fx0A instruction
+    pub pc: u16,
+
+    pub register: usize,
+
+    // Keys that were held when the wait began. They're ignored until they are released.
+    pub held: [bool; 16],
+
+    // The key that was pressed, when waiting for it to be released
+    pub pressed: Option<u8>,
+}
+
+impl KeyWait {
+    pub fn new(pc: u16, register: usize, keys: &[bool; 16]) -> KeyWait {
+        KeyWait { pc, register, held: *keys, pressed: None }
+    }
+
+    // Looks at the keys for this cycle. Returns the key to store once the wait is over, which
+    // is when a key is pressed, or when it's released again if wait_for_release is set.
+    pub fn update(&mut self, keys: &[bool; 16], wait_for_release: bool) -> Option<u8> {
+        for (held, pressed) in self.held.iter_mut().zip(keys.iter()) {
+            *held = *held && *pressed;
+        }
+
+        match self.pressed {
+            None => {
+                let key = (0..16).find(|key| keys[*key] && !self.held[*key])? as u8;
+                self.pressed = Some(key);
+                if wait_for_release {
+                    None
+                } else {
+                    Some(key)
+                }
+            }
+            Some(key) if !keys[key as usize] => Some(key),
+            Some(_) => None,
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn waits_for_a_new_press_and_release() {
+        let mut keys = [false; 16];
+        keys[3] = true;
+        let mut wait = KeyWait::new(0x200, 0, &keys);
+
+        // 3 was already held, so it has to be let go and pressed again
+        assert_eq!(wait.update(&keys, true), None);
+        keys[3] = false;
+        keys[9] = true;
+        assert_eq!(wait.update(&keys, true), None);
+        assert_eq!(wait.pressed, Some(9));
+        keys[3] = true;
+        assert_eq!(wait.update(&keys, true), None);
+        keys[9] = false;
+        assert_eq!(wait.update(&keys, true), Some(9));
+
+        let mut wait = KeyWait::new(0x200, 0, &[false; 16]);
+        assert_eq!(wait.update(&keys, false), Some(3));
+    }
+
+}
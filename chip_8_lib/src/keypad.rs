@@ -0,0 +1,162 @@
+use std::collections::BTreeMap;
+
+use crate::checksum;
+use crate::rom_database;
+
+const HEADER: &str = "chip8-keypad 1";
+
+// CHIP-8 Keypad    QWERTY           AZERTY           Numpad
+// +-+-+-+-+        +-+-+-+-+        +-+-+-+-+        +-+-+-+-+
+// |1|2|3|C|        |1|2|3|4|        |1|2|3|4|        |1|2|3|-|
+// +-+-+-+-+        +-+-+-+-+        +-+-+-+-+        +-+-+-+-+
+// |4|5|6|D|        |Q|W|E|R|        |A|Z|E|R|        |4|5|6|+|
+// +-+-+-+-+        +-+-+-+-+        +-+-+-+-+        +-+-+-+-+
+// |7|8|9|E|        |A|S|D|F|        |Q|S|D|F|        |7|8|9|Enter
+// +-+-+-+-+        +-+-+-+-+        +-+-+-+-+        +-+-+-+-+
+// |A|0|B|F|        |Z|X|C|V|        |W|X|C|V|        |/|0|*|.|
+// +-+-+-+-+        +-+-+-+-+        +-+-+-+-+        +-+-+-+-+
+//
+// The QWERTY and AZERTY layouts put the keypad where it sits on the keyboard. The numpad layout
+// keeps the digits as they are, so the keys match the numbers printed on them.
+const KEYPAD_ROWS: [[u8; 4]; 4] = [[0x1, 0x2, 0x3, 0xC], [0x4, 0x5, 0x6, 0xD], [0x7, 0x8, 0x9, 0xE], [0xA, 0x0, 0xB, 0xF]];
+const QWERTY_ROWS: [[&str; 4]; 4] = [["1", "2", "3", "4"], ["q", "w", "e", "r"], ["a", "s", "d", "f"], ["z", "x", "c", "v"]];
+const AZERTY_ROWS: [[&str; 4]; 4] = [["1", "2", "3", "4"], ["a", "z", "e", "r"], ["q", "s", "d", "f"], ["w", "x", "c", "v"]];
+const NUMPAD_ROWS: [[&str; 4]; 4] = [["1", "2", "3", "-"], ["4", "5", "6", "+"], ["7", "8", "9", "Enter"], ["/", "0", "*", "."]];
+
+// Maps keys on the host keyboard to keys on the CHIP-8 keypad. Host keys are named the way
+// browsers name them in KeyboardEvent.key, except that letters are lower case and the space bar
+// is `Space`, so the names can be written in a config file:
+//
+//     chip8-keypad 1
+//     name qwerty
+//     1 1
+//     q 4
+//     ArrowLeft 4
+//
+// After the header and name each line is a host key and the hexadecimal CHIP-8 key it presses.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Keypad {
+    pub name: String,
+    pub keys: BTreeMap<String, u8>,
+}
+
+impl Keypad {
+    pub const LAYOUTS: [&'static str; 3] = ["qwerty", "azerty", "numpad"];
+
+    pub fn from_name(name: &str) -> Option<Keypad> {
+        let rows = match name {
+            "qwerty" => QWERTY_ROWS,
+            "azerty" => AZERTY_ROWS,
+            "numpad" => NUMPAD_ROWS,
+            _ => return None,
+        };
+        let mut keys = BTreeMap::new();
+        for (host_row, keypad_row) in rows.iter().zip(KEYPAD_ROWS.iter()) {
+            for (host_key, key) in host_row.iter().zip(keypad_row.iter()) {
+                keys.insert(host_key.to_string(), *key);
+            }
+        }
+        Some(Keypad { name: name.to_string(), keys })
+    }
+
+    // This keypad with the ROM database's keys for the ROM mapped on top
+    pub fn for_rom(&self, rom: &[u8]) -> Keypad {
+        let mut keypad = self.clone();
+        if let Some(entry) = rom_database::lookup(checksum::crc32(rom)) {
+            for (host_key, key) in entry.keys.iter() {
+                keypad.keys.insert(host_key.to_string(), *key);
+            }
+        }
+        keypad
+    }
+
+    // The CHIP-8 key a host key presses, if any
+    pub fn key(&self, host_key: &str) -> Option<u8> {
+        self.keys.get(&normalize(host_key)).cloned()
+    }
+
+    pub fn to_text(&self) -> String {
+        let mut lines = vec![String::from(HEADER), format!("name {}", self.name)];
+        for (host_key, key) in self.keys.iter() {
+            lines.push(format!("{} {:X}", host_key, key));
+        }
+        lines.join("\n") + "\n"
+    }
+
+    pub fn parse(text: &str) -> Result<Keypad, String> {
+        let mut lines = text.lines().enumerate().filter(|(_, line)| !line.trim().is_empty());
+
+        match lines.next() {
+            Some((_, line)) if line.trim() == HEADER => {}
+            _ => return Err(format!("expected `{}` on the first line", HEADER)),
+        }
+        let name = match lines.next() {
+            Some((_, line)) if line.trim().starts_with("name ") => line.trim()[5..].trim().to_string(),
+            Some((idx, _)) => return Err(format!("line {}: expected `name`", idx + 1)),
+            None => return Err(String::from("missing `name`")),
+        };
+
+        let mut keys = BTreeMap::new();
+        for (idx, line) in lines {
+            let parts: Vec<&str> = line.split_whitespace().collect();
+            match parts.as_slice() {
+                [host_key, key] => match u8::from_str_radix(key, 16) {
+                    Ok(key) if key < 16 => {
+                        keys.insert(normalize(host_key), key);
+                    }
+                    _ => return Err(format!("line {}: {} isn't a CHIP-8 key", idx + 1, key)),
+                },
+                _ => return Err(format!("line {}: expected `<host key> <CHIP-8 key>`", idx + 1)),
+            }
+        }
+        Ok(Keypad { name, keys })
+    }
+}
+
+impl Default for Keypad {
+    fn default() -> Self {
+        Keypad::from_name("qwerty").unwrap()
+    }
+}
+
+fn normalize(host_key: &str) -> String {
+    match host_key {
+        " " => String::from("Space"),
+        _ if host_key.chars().count() == 1 => host_key.to_lowercase(),
+        _ => host_key.to_string(),
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn maps_host_keys_to_the_keypad() {
+        let qwerty = Keypad::default();
+        assert_eq!((qwerty.key("4"), qwerty.key("Q"), qwerty.key("x"), qwerty.key("ArrowLeft")), (Some(0xC), Some(0x4), Some(0x0), None));
+
+        let azerty = Keypad::from_name("azerty").unwrap();
+        assert_eq!((azerty.key("a"), azerty.key("q"), azerty.key("w")), (Some(0x4), Some(0x7), Some(0xA)));
+
+        let numpad = Keypad::from_name("numpad").unwrap();
+        assert_eq!((numpad.key("4"), numpad.key("Enter"), numpad.key("/")), (Some(0x4), Some(0xE), Some(0xA)));
+
+        // Space Invaders moves with 4 and 6 and shoots with 5
+        let rom = std::fs::read(concat!(env!("CARGO_MANIFEST_DIR"), "/../chip_8_wasm/static/roms/INVADERS")).unwrap();
+        let keypad = qwerty.for_rom(&rom);
+        assert_eq!((keypad.key("ArrowLeft"), keypad.key(" "), keypad.key("q")), (Some(0x4), Some(0x5), Some(0x4)));
+    }
+
+    #[test]
+    fn config_round_trips() {
+        let mut keypad = Keypad::from_name("azerty").unwrap();
+        keypad.keys.insert(String::from("Space"), 0x5);
+        let text = keypad.to_text();
+        assert!(text.starts_with("chip8-keypad 1\nname azerty\n1 1\n2 2\n3 3\n4 C\nSpace 5\n"));
+        assert_eq!(Keypad::parse(&text).unwrap(), keypad);
+        assert!(Keypad::parse("chip8-keypad 1\nname mine\nq 10\n").is_err());
+    }
+
+}
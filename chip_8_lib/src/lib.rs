@@ -1,3 +1,33 @@
+pub mod batch;
+pub mod cheat;
+pub mod cheat_search;
+pub mod checksum;
+pub mod coverage;
 pub mod cpu;
+pub mod crt;
+pub mod debug_state;
+pub mod debugger;
+pub mod dirty_rect;
 pub mod emulate_cycle_error;
 pub mod disassembler;
+pub mod frame_result;
+pub mod gif;
+pub mod gif_recorder;
+pub mod golden;
+pub mod instruction;
+pub mod json;
+pub mod key_event;
+pub mod key_wait;
+pub mod keypad;
+pub mod movie;
+pub mod palette;
+pub mod persistence;
+pub mod png;
+pub mod profiler;
+pub mod quirks;
+pub mod renderer;
+pub mod rng;
+pub mod rom_database;
+pub mod rom_info;
+pub mod save_state;
+pub mod save_state_error;
@@ -1,31 +1,545 @@
+use std::env;
 use std::io;
 use std::io::prelude::*;
+use std::fs;
 use std::fs::File;
+use std::path::Path;
+use std::process;
+use std::thread;
+use std::time::Instant;
 
-use chip_8_lib::cpu::Cpu;
+use chip_8_lib::batch::{self, BatchConfig, BatchJob};
+use chip_8_lib::cheat::{self, Cheat, CheatTiming};
+use chip_8_lib::checksum;
+use chip_8_lib::coverage::{Coverage, SourceMap};
+use chip_8_lib::cpu::{Cpu, CYCLES_PER_FRAME};
+use chip_8_lib::crt::Crt;
+use chip_8_lib::debugger::{self, Debugger};
+use chip_8_lib::frame_result::FrameResult;
+use chip_8_lib::gif_recorder::GifRecorder;
+use chip_8_lib::golden;
+use chip_8_lib::keypad::Keypad;
+use chip_8_lib::movie::{mask_to_keys, Movie};
+use chip_8_lib::palette::Palette;
+use chip_8_lib::persistence::Persistence;
+use chip_8_lib::png;
+use chip_8_lib::profiler::Profiler;
+use chip_8_lib::quirks::Quirks;
+use chip_8_lib::renderer::{self, Renderer};
+use chip_8_lib::rng::Rng;
+use chip_8_lib::rom_info::RomInfo;
+
+const DEFAULT_ROM: &str = "./chip_8_wasm/static/roms/PONG2";
+const DEFAULT_ROMS_DIR: &str = "./chip_8_wasm/static/roms";
+const DEFAULT_GOLDEN_DIR: &str = "./chip_8_lib/tests/golden";
+
+const USAGE: &str = "Usage:
+  chip8 [run] [rom] [--cheat FILE]...
+                      Run a ROM with no display (PONG2 by default), with the cheats for it in FILE
+  chip8 info <rom>    Print a static report about a ROM without running it
+  chip8 profile <rom> [--cycles N] [--top N] [--json]
+                      Run a ROM for N cycles (100000 by default) and report where they were spent
+  chip8 coverage <rom> [--cycles N] [--lcov FILE] [--source-map FILE]
+                      Run a ROM for N cycles and print a disassembly annotated with what was executed,
+                      optionally writing an lcov file mapped back to the assembler source
+  chip8 bench <rom> [--millions N]
+                      Run a ROM headlessly for N million instructions (10 by default) and report the speed
+  chip8 batch <rom or directory>... [--profiles P,...] [--frames N] [--threads N] [--seed N] [--csv]
+                      Run every ROM with every quirk profile (default, vip, schip) for N frames
+                      (600 by default) across threads, and print a JSON (or CSV) report
+  chip8 play <rom> <movie>
+                      Play back an input movie recorded in the browser and print the final screen
+  chip8 golden [--bless] [--roms DIR] [--goldens DIR]
+                      Compare the final screen of every bundled ROM against its golden image,
+                      or rewrite the golden images that changed with --bless
+  chip8 debug <rom> [--cheat FILE]... [--keypad LAYOUT or FILE]
+                      Step through a ROM, inspect and edit memory and registers (`help` lists commands).
+                      The keypad layout (qwerty, azerty or numpad) is used by the `press` command
+  chip8 screenshot <rom> <png> [--movie FILE] [--frames N] [--palette P] [--scale N] [--persistence F] [--crt E]
+                      Run a ROM for N frames (600 by default), or through a movie, and save the screen
+                      as a PNG. The palette is default, green, amber, lcd or colours like #000000,#FFFFFF,
+                      the persistence filter off, deflicker or phosphor[:DECAY] to hide flicker, and the
+                      CRT effects off, crt, or a list like scanlines=0.5,gaps,bloom,curvature=0.1
+  chip8 gif <rom> <gif> [--movie FILE] [--frames N] [--palette P] [--scale N] [--persistence F] [--crt E]
+                      Like screenshot, but records every frame as an animated GIF";
 
 // This file is mostly used for debugging, you shouldn't need to use it
 fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+
+    match args.first().map(String::as_str) {
+        None => run(DEFAULT_ROM, &[]),
+        Some("run") => run(positional(&args[1..], &["--cheat"]).first().unwrap_or(&DEFAULT_ROM), &args[1..]),
+        Some("info") => match args.get(1) {
+            Some(file_name) => info(file_name),
+            None => usage(),
+        },
+        Some("profile") => match args.get(1) {
+            Some(file_name) => profile(file_name, &args[2..]),
+            None => usage(),
+        },
+        Some("coverage") => match args.get(1) {
+            Some(file_name) => coverage(file_name, &args[2..]),
+            None => usage(),
+        },
+        Some("bench") => match args.get(1) {
+            Some(file_name) => bench(file_name, &args[2..]),
+            None => usage(),
+        },
+        Some("batch") => batch(&args[1..]),
+        Some("play") => match (args.get(1), args.get(2)) {
+            (Some(file_name), Some(movie_file)) => play(file_name, movie_file),
+            _ => usage(),
+        },
+        Some("golden") => golden(&args[1..]),
+        Some("debug") => match args.get(1) {
+            Some(file_name) => debug(file_name, &args[2..]),
+            None => usage(),
+        },
+        Some("screenshot") => match (args.get(1), args.get(2)) {
+            (Some(file_name), Some(png_file)) => screenshot(file_name, png_file, &args[3..]),
+            _ => usage(),
+        },
+        Some("gif") => match (args.get(1), args.get(2)) {
+            (Some(file_name), Some(gif_file)) => gif(file_name, gif_file, &args[3..]),
+            _ => usage(),
+        },
+        Some(_) => usage(),
+    }
+}
+
+fn usage() {
+    eprintln!("{}", USAGE);
+    process::exit(1);
+}
+
+fn run(file_name: &str, options: &[String]) {
     println!("Starting CPU");
 
     let mut cpu = Cpu::new();
     cpu.initialize();
 
-    let data = load_game("./chip_8_wasm/static/roms/PONG2").unwrap();
+    println!("load_game() {}", file_name);
+    let data = load_game(file_name).unwrap();
+    println!("{} is {} bytes in size", file_name, data.len());
+    let cheats = load_cheats(options, &data);
     cpu.load_game(data);
+    cheat::apply(&cheats, CheatTiming::OnLoad, &mut cpu);
 
     loop {
-        // Emulate one cycle
-        cpu.emulate_cycle().unwrap();
+        cheat::apply(&cheats, CheatTiming::EveryFrame, &mut cpu);
+        if let FrameResult::Error { pc, message, .. } = cpu.run_frame() {
+            eprintln!("Stopped at 0x{:X}: {}", pc, message);
+            process::exit(1);
+        }
     }
 }
 
+fn info(file_name: &str) {
+    println!("{}", RomInfo::analyze(&read_rom(file_name)));
+}
+
+fn profile(file_name: &str, options: &[String]) {
+    let cycles = number_option(options, "--cycles").unwrap_or(100_000);
+    let top = number_option(options, "--top").unwrap_or(20) as usize;
 
-fn load_game(file_name: &str) -> io::Result<Vec<u8>> {
-    println!("load_game() {}", file_name);
+    let mut cpu = Cpu::new();
+    cpu.load_game(read_rom(file_name));
+    cpu.profiler = Some(Profiler::new());
+    run_cycles(&mut cpu, cycles);
+
+    let profiler = cpu.profiler.unwrap();
+    if options.iter().any(|option| option == "--json") {
+        println!("{}", profiler.to_json());
+    } else {
+        println!("{}", profiler.report(top));
+    }
+}
+
+fn coverage(file_name: &str, options: &[String]) {
+    let cycles = number_option(options, "--cycles").unwrap_or(100_000);
+    let rom = read_rom(file_name);
+
+    let mut cpu = Cpu::new();
+    cpu.load_game(rom.clone());
+    cpu.coverage = Some(Coverage::new());
+    run_cycles(&mut cpu, cycles);
+
+    let coverage = cpu.coverage.unwrap();
+    println!("{}", coverage.listing(&rom));
+
+    if let Some(lcov_file) = string_option(options, "--lcov") {
+        let source_map = string_option(options, "--source-map").map(|map_file| {
+            let text = fs::read_to_string(map_file).unwrap_or_else(|e| {
+                eprintln!("Could not read {}: {}", map_file, e);
+                process::exit(1);
+            });
+            SourceMap::parse(&text).unwrap_or_else(|e| {
+                eprintln!("{}: {}", map_file, e);
+                process::exit(1);
+            })
+        });
+
+        if let Err(e) = fs::write(lcov_file, coverage.lcov(&rom, file_name, source_map.as_ref())) {
+            eprintln!("Could not write {}: {}", lcov_file, e);
+            process::exit(1);
+        }
+    }
+}
+
+fn bench(file_name: &str, options: &[String]) {
+    let instructions = number_option(options, "--millions").unwrap_or(10) * 1_000_000;
+
+    let mut cpu = Cpu::new();
+    cpu.rng = Some(Rng::new(1));
+    cpu.load_game(read_rom(file_name));
+
+    let start = Instant::now();
+    let frames = run_cycles(&mut cpu, instructions);
+    let seconds = start.elapsed().as_secs_f64();
+    let executed = cpu.cycle;
+
+    println!("Instructions: {}", executed);
+    println!("Frames:       {}", frames);
+    println!("Time:         {:.3}s", seconds);
+    println!("Speed:        {:.0} instructions/s, {:.0} frames/s ({:.0}x real time)",
+        executed as f64 / seconds,
+        frames as f64 / seconds,
+        frames as f64 / seconds / 60.0
+    );
+}
+
+fn batch(options: &[String]) {
+    let mut roms = vec![];
+    for path in positional(options, &["--profiles", "--frames", "--threads", "--seed"]) {
+        if Path::new(path).is_dir() {
+            let mut files: Vec<String> = fs::read_dir(path)
+                .unwrap_or_else(|e| {
+                    eprintln!("Could not read {}: {}", path, e);
+                    process::exit(1);
+                })
+                .filter_map(|entry| entry.ok())
+                .filter(|entry| entry.path().is_file())
+                .map(|entry| entry.path().to_string_lossy().to_string())
+                .collect();
+            files.sort();
+            roms.extend(files.into_iter().map(|file| {
+                let rom = read_rom(&file);
+                (file, rom)
+            }));
+        } else {
+            roms.push((path.to_string(), read_rom(path)));
+        }
+    }
+    if roms.is_empty() {
+        usage();
+    }
+
+    let profile_names = string_option(options, "--profiles").unwrap_or("default,vip,schip");
+    let profiles: Vec<(String, Quirks)> = profile_names
+        .split(',')
+        .map(|name| match Quirks::from_name(name) {
+            Some(quirks) => (name.to_string(), quirks),
+            None => {
+                eprintln!("Unknown quirk profile {}", name);
+                process::exit(1);
+            }
+        })
+        .collect();
+
+    let config = BatchConfig {
+        frames: number_option(options, "--frames").unwrap_or(600),
+        threads: number_option(options, "--threads")
+            .map(|threads| threads as usize)
+            .unwrap_or_else(|| thread::available_parallelism().map(|n| n.get()).unwrap_or(1)),
+        seed: number_option(options, "--seed").unwrap_or(1),
+    };
+
+    let results = batch::run_batch(&BatchJob::cross(&roms, &profiles), &config);
+    if options.iter().any(|option| option == "--csv") {
+        print!("{}", batch::to_csv(&results));
+    } else {
+        println!("{}", batch::to_json(&results));
+    }
+}
+
+fn play(file_name: &str, movie_file: &str) {
+    let rom = read_rom(file_name);
+    let movie = read_movie(movie_file, &rom);
+
+    let mut cpu = Cpu::new();
+    cpu.load_game(rom);
+    if let Err(e) = movie.play(&mut cpu) {
+        eprintln!("Stopped at 0x{:X}: {}", cpu.pc, e);
+    }
+
+    for row in cpu.display.chunks(64) {
+        println!("{}", row.iter().map(|pixel| if *pixel == 1 { '#' } else { '.' }).collect::<String>());
+    }
+    println!("Frames: {}", movie.frames.len());
+    println!("Display CRC-32: {:08X}", checksum::crc32(&cpu.display));
+}
+
+fn golden(options: &[String]) {
+    let roms_dir = string_option(options, "--roms").unwrap_or(DEFAULT_ROMS_DIR);
+    let golden_dir = string_option(options, "--goldens").unwrap_or(DEFAULT_GOLDEN_DIR);
+    let bless = options.iter().any(|option| option == "--bless");
+
+    let mismatches = golden::check(Path::new(roms_dir), Path::new(golden_dir), bless).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        process::exit(1);
+    });
+    for mismatch in mismatches.iter() {
+        println!("{}\n", mismatch);
+    }
+    if bless {
+        println!("Blessed {} golden images", mismatches.len());
+    } else if mismatches.is_empty() {
+        println!("All ROMs match their golden images");
+    } else {
+        println!("{} ROMs don't match their golden images", mismatches.len());
+        process::exit(1);
+    }
+}
+
+fn debug(file_name: &str, options: &[String]) {
+    let rom = read_rom(file_name);
+    let cheats = load_cheats(options, &rom);
+    let keypad = load_keypad(options).for_rom(&rom);
+    let mut cpu = Cpu::new();
+    cpu.load_game(rom);
+    cheat::apply(&cheats, CheatTiming::OnLoad, &mut cpu);
+    let mut debugger = Debugger::new(cpu);
+    debugger.cheats = cheats;
+    debugger.keypad = keypad;
+    println!("{}\n\nq to quit", debugger::HELP);
 
+    let stdin = io::stdin();
+    loop {
+        print!("(chip8) ");
+        io::stdout().flush().unwrap();
+        let mut line = String::new();
+        if stdin.lock().read_line(&mut line).unwrap_or(0) == 0 || line.trim() == "q" {
+            break;
+        }
+        match debugger.command(&line) {
+            Ok(output) if output.is_empty() => {}
+            Ok(output) => println!("{}", output),
+            Err(e) => println!("Error: {}", e),
+        }
+    }
+}
+
+fn screenshot(file_name: &str, png_file: &str, options: &[String]) {
+    let mut renderer = load_renderer(options);
+    run_frames(file_name, options, |cpu| {
+        renderer.update(&cpu.display);
+    });
+    let image = png::encode(renderer.width(), renderer.height(), &renderer.render());
+    write_file(png_file, &image);
+}
+
+fn gif(file_name: &str, gif_file: &str, options: &[String]) {
+    let mut recorder = GifRecorder::new(load_renderer(options));
+    run_frames(file_name, options, |cpu| recorder.record_frame(&cpu.display));
+    write_file(gif_file, &recorder.finish());
+}
+
+// Reads a movie, exiting with an error if it can't be parsed or was recorded with another ROM
+fn read_movie(movie_file: &str, rom: &[u8]) -> Movie {
+    let text = fs::read_to_string(movie_file).unwrap_or_else(|e| {
+        eprintln!("Could not read {}: {}", movie_file, e);
+        process::exit(1);
+    });
+    let movie = Movie::parse(&text).unwrap_or_else(|e| {
+        eprintln!("{}: {}", movie_file, e);
+        process::exit(1);
+    });
+    if !movie.matches_rom(rom) {
+        eprintln!("{} was recorded with a different ROM (CRC-32 {:08X})", movie_file, movie.rom_crc32);
+        process::exit(1);
+    }
+    movie
+}
+
+// Runs a ROM through the movie given by `--movie`, or for `--frames` frames without pressing
+// any keys, calling on_frame after every frame
+fn run_frames(file_name: &str, options: &[String], mut on_frame: impl FnMut(&Cpu)) {
+    let rom = read_rom(file_name);
+    let movie = match string_option(options, "--movie") {
+        Some(movie_file) => read_movie(movie_file, &rom),
+        None => {
+            let mut movie = Movie::new(&rom, golden::SEED, Quirks::DEFAULT);
+            for _ in 0..number_option(options, "--frames").unwrap_or(golden::FRAMES as u64) {
+                movie.record_frame(&[false; 16]);
+            }
+            movie
+        }
+    };
+
+    let mut cpu = Cpu::new();
+    cpu.load_game(rom);
+    movie.start(&mut cpu);
+    for mask in movie.frames.iter() {
+        if !movie.has_events() {
+            cpu.keys = mask_to_keys(*mask);
+        }
+        if let FrameResult::Error { pc, message, .. } = cpu.run_frame() {
+            eprintln!("Stopped at 0x{:X}: {}", pc, message);
+            break;
+        }
+        on_frame(&cpu);
+    }
+}
+
+// Runs frames until `cycles` instructions have been executed or there's an error, returning
+// how many frames that took. Frames that end early waiting for a key run fewer instructions,
+// so the count comes from the cpu rather than the number of frames.
+fn run_cycles(cpu: &mut Cpu, cycles: u64) -> u64 {
+    let end = cpu.cycle + cycles;
+    let mut frames = 0;
+    while cpu.cycle < end {
+        let remaining = (end - cpu.cycle).min(CYCLES_PER_FRAME as u64) as usize;
+        if let FrameResult::Error { pc, message, .. } = cpu.run_frame_cycles(remaining) {
+            eprintln!("Stopped at 0x{:X} after {} frames: {}", pc, frames, message);
+            break;
+        }
+        frames += 1;
+    }
+    frames
+}
+
+// Arguments that aren't options or the values of options
+fn positional<'a>(options: &'a [String], value_options: &[&str]) -> Vec<&'a str> {
+    let mut values = vec![];
+    let mut idx = 0;
+    while idx < options.len() {
+        if value_options.contains(&options[idx].as_str()) {
+            idx += 2;
+            continue;
+        }
+        if !options[idx].starts_with("--") {
+            values.push(options[idx].as_str());
+        }
+        idx += 1;
+    }
+    values
+}
+
+// The cheats for this ROM in every `--cheat FILE`, exiting with an error message if a file
+// can't be read
+fn load_cheats(options: &[String], rom: &[u8]) -> Vec<Cheat> {
+    let mut cheats = vec![];
+    for (idx, _) in options.iter().enumerate().filter(|(_, option)| *option == "--cheat") {
+        let file_name = string_option(&options[idx..], "--cheat").unwrap();
+        let text = fs::read_to_string(file_name).unwrap_or_else(|e| {
+            eprintln!("Could not read {}: {}", file_name, e);
+            process::exit(1);
+        });
+        let parsed = cheat::parse(&text).unwrap_or_else(|e| {
+            eprintln!("{}: {}", file_name, e);
+            process::exit(1);
+        });
+        let for_rom = cheat::for_rom(parsed, rom);
+        if for_rom.is_empty() {
+            eprintln!("{} has no cheats for this ROM (CRC-32 {:08X})", file_name, checksum::crc32(rom));
+        }
+        cheats.extend(for_rom);
+    }
+    cheats
+}
+
+// The layout named by `--keypad`, or read from the config file it names. QWERTY by default.
+fn load_keypad(options: &[String]) -> Keypad {
+    let name = match string_option(options, "--keypad") {
+        Some(name) => name,
+        None => return Keypad::default(),
+    };
+    if let Some(keypad) = Keypad::from_name(name) {
+        return keypad;
+    }
+    let text = fs::read_to_string(name).unwrap_or_else(|e| {
+        eprintln!("{} isn't one of the layouts {}, and could not be read: {}", name, Keypad::LAYOUTS.join(", "), e);
+        process::exit(1);
+    });
+    Keypad::parse(&text).unwrap_or_else(|e| {
+        eprintln!("{}: {}", name, e);
+        process::exit(1);
+    })
+}
+
+// A renderer with the palette, scale, persistence filter and CRT effects given by `--palette`,
+// `--scale`, `--persistence` and `--crt`
+fn load_renderer(options: &[String]) -> Renderer {
+    let palette = match string_option(options, "--palette") {
+        Some(text) => Palette::from_name_or_colors(text).unwrap_or_else(|e| {
+            eprintln!("--palette: {}", e);
+            process::exit(1);
+        }),
+        None => Palette::default(),
+    };
+    let scale = renderer::check_scale(number_option(options, "--scale").unwrap_or(4) as usize).unwrap_or_else(|e| {
+        eprintln!("--scale: {}", e);
+        process::exit(1);
+    });
+    let mut renderer = Renderer::new(palette, scale);
+    if let Some(text) = string_option(options, "--persistence") {
+        renderer.persistence = Persistence::parse(text).unwrap_or_else(|e| {
+            eprintln!("--persistence: {}", e);
+            process::exit(1);
+        });
+    }
+    if let Some(text) = string_option(options, "--crt") {
+        renderer.crt = Crt::parse(text).unwrap_or_else(|e| {
+            eprintln!("--crt: {}", e);
+            process::exit(1);
+        });
+    }
+    renderer
+}
+
+fn write_file(file_name: &str, data: &[u8]) {
+    if let Err(e) = fs::write(file_name, data) {
+        eprintln!("Could not write {}: {}", file_name, e);
+        process::exit(1);
+    }
+}
+
+// Reads a ROM file, exiting with an error message if it can't be read
+fn read_rom(file_name: &str) -> Vec<u8> {
+    load_game(file_name).unwrap_or_else(|e| {
+        eprintln!("Could not read {}: {}", file_name, e);
+        process::exit(1);
+    })
+}
+
+// The value following an option like `--lcov coverage.info`
+fn string_option<'a>(options: &'a [String], name: &str) -> Option<&'a str> {
+    let idx = options.iter().position(|option| option == name)?;
+    match options.get(idx + 1) {
+        Some(value) => Some(value),
+        None => {
+            eprintln!("{} expects a value", name);
+            process::exit(1);
+        }
+    }
+}
+
+// The value following an option like `--cycles 1000`
+fn number_option(options: &[String], name: &str) -> Option<u64> {
+    match string_option(options, name).map(|value| value.parse()) {
+        None => None,
+        Some(Ok(value)) => Some(value),
+        Some(Err(_)) => {
+            eprintln!("{} expects a number", name);
+            process::exit(1);
+        }
+    }
+}
+
+
+fn load_game(file_name: &str) -> io::Result<Vec<u8>> {
     let file_metadata = std::fs::metadata(file_name)?;
-    println!("{} is {} bytes in size", file_name, file_metadata.len());
 
     let mut f = File::open(file_name)?;
     let mut buffer: Vec<u8> = vec![0; file_metadata.len() as usize];
@@ -0,0 +1,330 @@
+use crate::checksum;
+use crate::cpu::Cpu;
+use crate::emulate_cycle_error::EmulateCycleError;
+use crate::frame_result::FrameResult;
+use crate::key_event::KeyEvent;
+use crate::quirks::Quirks;
+use crate::rng::Rng;
+
+const HEADER: &str = "chip8-movie 1";
+
+// Movies with key events
+const EVENTS_HEADER: &str = "chip8-movie 2";
+
+// Movies can't be longer than a day at 60 frames a second, so a corrupt run length can't
+// make parse() allocate more memory than there is
+pub const MAX_FRAMES: usize = 60 * 60 * 60 * 24;
+
+// Names used for the quirks in movie files
+const QUIRK_NAMES: [&str; 6] = [
+    "shift_uses_vy",
+    "load_store_increments_i",
+    "jump_uses_vx",
+    "logic_resets_vf",
+    "clip_sprites",
+    "wait_for_release",
+];
+
+// A recording of the keypad state for every frame of a game, along with everything else needed
+// to replay it exactly: the ROM it was recorded with, the random number seed and the quirks.
+//
+// Movies are saved as text so they are easy to share and diff:
+//
+//     chip8-movie 1
+//     rom 7D75A857
+//     seed 1
+//     quirks
+//     120 0000
+//     15 0012
+//
+// After the header each line is a number of frames followed by the keys held during them, as a
+// hexadecimal bit mask with key 0 in the lowest bit.
+//
+// Movies recorded from key events are version 2, and list every event after the frames with
+// the cycle it was applied at, the key and whether it was pressed or released:
+//
+//     key 1520 4 down
+//     key 1533 4 up
+//
+// Those are played back instead of the bit masks, so presses that were over within a frame
+// play back exactly too.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Movie {
+    pub rom_crc32: u32,
+    pub seed: u64,
+    pub quirks: Quirks,
+
+    // Keys held during each frame, as a bit mask. This is also what sets the movie's length.
+    pub frames: Vec<u16>,
+
+    // Key events with the cycles they happened at, empty for movies recorded a frame at a time
+    pub events: Vec<KeyEvent>,
+}
+
+pub fn keys_to_mask(keys: &[bool; 16]) -> u16 {
+    keys.iter()
+        .enumerate()
+        .filter(|(_, pressed)| **pressed)
+        .fold(0, |mask, (key, _)| mask | 1 << key)
+}
+
+pub fn mask_to_keys(mask: u16) -> [bool; 16] {
+    let mut keys = [false; 16];
+    for (key, pressed) in keys.iter_mut().enumerate() {
+        *pressed = mask & (1 << key) != 0;
+    }
+    keys
+}
+
+fn quirk_flags(quirks: &Quirks) -> [bool; 6] {
+    [
+        quirks.shift_uses_vy,
+        quirks.load_store_increments_i,
+        quirks.jump_uses_vx,
+        quirks.logic_resets_vf,
+        quirks.clip_sprites,
+        quirks.wait_for_release,
+    ]
+}
+
+impl Movie {
+    pub fn new(rom: &[u8], seed: u64, quirks: Quirks) -> Movie {
+        Movie {
+            rom_crc32: checksum::crc32(rom),
+            seed,
+            quirks,
+            frames: vec![],
+            events: vec![],
+        }
+    }
+
+    // Sets up a freshly loaded Cpu the way the movie was recorded, queueing its key events
+    pub fn start(&self, cpu: &mut Cpu) {
+        cpu.rng = Some(Rng::new(self.seed));
+        cpu.quirks = self.quirks;
+        cpu.key_events.extend(self.events.iter().cloned());
+    }
+
+    // Movies without events set the keys at the start of every frame instead
+    pub fn has_events(&self) -> bool {
+        !self.events.is_empty()
+    }
+
+    pub fn record_frame(&mut self, keys: &[bool; 16]) {
+        self.frames.push(keys_to_mask(keys));
+    }
+
+    pub fn keys_for_frame(&self, frame: usize) -> Option<[bool; 16]> {
+        self.frames.get(frame).map(|mask| mask_to_keys(*mask))
+    }
+
+    pub fn matches_rom(&self, rom: &[u8]) -> bool {
+        checksum::crc32(rom) == self.rom_crc32
+    }
+
+    // Plays the whole movie on a Cpu that has just had the movie's ROM loaded
+    pub fn play(&self, cpu: &mut Cpu) -> Result<(), EmulateCycleError> {
+        self.start(cpu);
+        for mask in self.frames.iter() {
+            if !self.has_events() {
+                cpu.keys = mask_to_keys(*mask);
+            }
+            if let FrameResult::Error { message, .. } = cpu.run_frame() {
+                return Err(EmulateCycleError { message });
+            }
+        }
+        Ok(())
+    }
+
+    pub fn to_text(&self) -> String {
+        let enabled: Vec<&str> = QUIRK_NAMES
+            .iter()
+            .zip(quirk_flags(&self.quirks).iter())
+            .filter(|(_, enabled)| **enabled)
+            .map(|(name, _)| *name)
+            .collect();
+
+        let mut lines = vec![
+            String::from(if self.has_events() { EVENTS_HEADER } else { HEADER }),
+            format!("rom {:08X}", self.rom_crc32),
+            format!("seed {}", self.seed),
+            format!("quirks {}", enabled.join(",")).trim_end().to_string(),
+        ];
+
+        // Run length encoded, keys are usually held for many frames
+        let mut idx = 0;
+        while idx < self.frames.len() {
+            let mask = self.frames[idx];
+            let run = self.frames[idx..].iter().take_while(|m| **m == mask).count();
+            lines.push(format!("{} {:04X}", run, mask));
+            idx += run;
+        }
+        for event in self.events.iter() {
+            let state = if event.pressed { "down" } else { "up" };
+            lines.push(format!("key {} {:X} {}", event.cycle.unwrap_or(0), event.key, state));
+        }
+
+        lines.join("\n") + "\n"
+    }
+
+    pub fn parse(text: &str) -> Result<Movie, String> {
+        let mut lines = text.lines().enumerate().filter(|(_, line)| !line.trim().is_empty());
+
+        let has_events = match lines.next() {
+            Some((_, line)) if line.trim() == HEADER => false,
+            Some((_, line)) if line.trim() == EVENTS_HEADER => true,
+            _ => return Err(format!("expected `{}` or `{}` on the first line", HEADER, EVENTS_HEADER)),
+        };
+
+        let mut field = |name: &str| -> Result<(usize, String), String> {
+            match lines.next() {
+                Some((idx, line)) => {
+                    let line = line.trim();
+                    match line.strip_prefix(name) {
+                        Some(value) if value.is_empty() || value.starts_with(' ') => Ok((idx, value.trim().to_string())),
+                        _ => Err(format!("line {}: expected `{}`", idx + 1, name)),
+                    }
+                }
+                None => Err(format!("missing `{}`", name)),
+            }
+        };
+
+        let (idx, rom) = field("rom")?;
+        let rom_crc32 = u32::from_str_radix(&rom, 16).map_err(|_| format!("line {}: bad ROM checksum", idx + 1))?;
+        let (idx, seed) = field("seed")?;
+        let seed = seed.parse().map_err(|_| format!("line {}: bad seed", idx + 1))?;
+        let (idx, names) = field("quirks")?;
+        let mut flags = [false; 6];
+        for name in names.split(',').map(str::trim).filter(|name| !name.is_empty()) {
+            match QUIRK_NAMES.iter().position(|quirk| *quirk == name) {
+                Some(quirk) => flags[quirk] = true,
+                None => return Err(format!("line {}: unknown quirk {}", idx + 1, name)),
+            }
+        }
+        let quirks = Quirks {
+            shift_uses_vy: flags[0],
+            load_store_increments_i: flags[1],
+            jump_uses_vx: flags[2],
+            logic_resets_vf: flags[3],
+            clip_sprites: flags[4],
+            wait_for_release: flags[5],
+        };
+
+        let mut frames = vec![];
+        let mut events = vec![];
+        for (idx, line) in lines {
+            let parts: Vec<&str> = line.split_whitespace().collect();
+            if let ["key", cycle, key, state] = parts.as_slice() {
+                if !has_events {
+                    return Err(format!("line {}: key events need a `{}` header", idx + 1, EVENTS_HEADER));
+                }
+                let event = match (cycle.parse::<u64>(), u8::from_str_radix(key, 16), *state) {
+                    (Ok(cycle), Ok(key), "down") if key < 16 => KeyEvent::press(key).at(cycle),
+                    (Ok(cycle), Ok(key), "up") if key < 16 => KeyEvent::release(key).at(cycle),
+                    _ => return Err(format!("line {}: expected `key <cycle> <key> down|up`", idx + 1)),
+                };
+                events.push(event);
+                continue;
+            }
+            let parsed = match parts.as_slice() {
+                [run, mask] => run.parse::<usize>().ok().zip(u16::from_str_radix(mask, 16).ok()),
+                _ => None,
+            };
+            match parsed {
+                Some((run, _)) if run > MAX_FRAMES - frames.len() => {
+                    return Err(format!("line {}: movies can't be longer than {} frames", idx + 1, MAX_FRAMES));
+                }
+                Some((run, mask)) => frames.extend(std::iter::repeat_n(mask, run)),
+                None => return Err(format!("line {}: expected `<frames> <keys>`", idx + 1)),
+            }
+        }
+
+        Ok(Movie { rom_crc32, seed, quirks, frames, events })
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn text_round_trips() {
+        let mut movie = Movie::new(&[0x12, 0x00], 42, Quirks::COSMAC_VIP);
+        let mut keys = [false; 16];
+        movie.record_frame(&keys);
+        movie.record_frame(&keys);
+        keys[1] = true;
+        keys[0xC] = true;
+        movie.record_frame(&keys);
+
+        let text = movie.to_text();
+        assert!(text.ends_with("seed 42\nquirks shift_uses_vy,load_store_increments_i,logic_resets_vf,clip_sprites,wait_for_release\n2 0000\n1 1002\n"));
+        assert_eq!(Movie::parse(&text).unwrap(), movie);
+        assert_eq!(movie.keys_for_frame(2), Some(keys));
+
+        let too_long = text.replace("2 0000\n", &format!("{} 0000\n", MAX_FRAMES));
+        assert!(Movie::parse(&too_long).unwrap_err().contains("can't be longer"));
+
+        // Key events are only read from version 2 movies
+        let error = Movie::parse(&(text.clone() + "key 10 4 down\n")).unwrap_err();
+        assert!(error.contains("chip8-movie 2"), "{}", error);
+        assert!(Movie::parse(&text.replace("movie 1", "movie 3")).unwrap_err().contains("`chip8-movie 1` or `chip8-movie 2`"));
+    }
+
+    #[test]
+    fn playback_is_deterministic() {
+        // Draws random sprites at random positions while key 0 is held
+        let rom = vec![0xC0, 0xFF, 0xC1, 0xFF, 0xF2, 0x29, 0xE5, 0x9E, 0x12, 0x00, 0xD0, 0x15, 0x12, 0x00];
+        let mut movie = Movie::new(&rom, 9, Quirks::DEFAULT);
+        for frame in 0..100 {
+            movie.record_frame(&mask_to_keys(if frame % 3 == 0 { 1 } else { 0 }));
+        }
+        let movie = Movie::parse(&movie.to_text()).unwrap();
+
+        let mut first = Cpu::new();
+        first.load_game(rom.clone());
+        movie.play(&mut first).unwrap();
+
+        let mut second = Cpu::new();
+        second.load_game(rom);
+        movie.play(&mut second).unwrap();
+
+        assert_eq!(&first.display[..], &second.display[..]);
+        assert!(first.display.contains(&1));
+    }
+
+    #[test]
+    fn replays_key_events_shorter_than_a_frame() {
+        // Counts presses of key 0 in V1, each has to be released before the next is counted
+        let rom = vec![0xF0, 0x0A, 0x71, 0x01, 0x12, 0x00];
+        let mut recording = Cpu::new();
+        recording.load_game(rom.clone());
+        let mut movie = Movie::new(&rom, 1, Quirks::COSMAC_VIP);
+        movie.start(&mut recording);
+        recording.key_log = Some(vec![]);
+        for frame in 0..20 {
+            if frame % 4 == 1 {
+                // Tapped and let go between two instructions
+                recording.push_key_event(KeyEvent::press(0));
+                recording.push_key_event(KeyEvent::release(0));
+            }
+            recording.run_frame();
+            movie.record_frame(&recording.keys);
+        }
+        movie.events = recording.key_log.take().unwrap();
+        assert_eq!(recording.v[1], 5);
+        assert_eq!(movie.frames.len(), 20);
+
+        let text = movie.to_text();
+        assert!(text.starts_with("chip8-movie 2\n"));
+        let movie = Movie::parse(&text).unwrap();
+        assert_eq!(movie.events.len(), 10);
+
+        let mut replay = Cpu::new();
+        replay.load_game(rom);
+        movie.play(&mut replay).unwrap();
+        assert_eq!(replay.save_state(), recording.save_state());
+    }
+
+}
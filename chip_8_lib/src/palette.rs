@@ -0,0 +1,82 @@
+// The colours the display is drawn with. XO-CHIP draws on two bit planes, so a pixel's value
+// is a bitmask of the planes it's lit on and indexes one of four colours: the background,
+// plane 1, plane 2 and both planes. Plain CHIP-8 only ever uses the first two.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Palette {
+    pub name: String,
+    pub colors: [[u8; 3]; 4],
+}
+
+impl Palette {
+    pub const NAMES: [&'static str; 4] = ["default", "green", "amber", "lcd"];
+
+    pub fn from_name(name: &str) -> Option<Palette> {
+        let colors = match name {
+            "default" => [[34, 35, 35], [240, 246, 240], [160, 164, 160], [100, 102, 100]],
+            "green" => [[8, 20, 10], [64, 255, 96], [24, 150, 50], [160, 255, 176]],
+            "amber" => [[20, 12, 4], [255, 176, 0], [170, 100, 0], [255, 220, 120]],
+            "lcd" => [[155, 188, 15], [15, 56, 15], [48, 98, 48], [139, 172, 15]],
+            _ => return None,
+        };
+        Some(Palette { name: name.to_string(), colors })
+    }
+
+    // A palette from two to four comma separated colours like `#000000,#FFFFFF`, background
+    // first. Missing plane colours are the plane 1 colour.
+    pub fn parse(text: &str) -> Result<Palette, String> {
+        let colors: Vec<[u8; 3]> = text.split(',').map(|color| parse_color(color.trim())).collect::<Result<_, _>>()?;
+        if colors.len() < 2 || colors.len() > 4 {
+            return Err(format!("expected 2 to 4 colours, got {}", colors.len()));
+        }
+        let mut palette = [colors[1]; 4];
+        palette[..colors.len()].copy_from_slice(&colors);
+        Ok(Palette { name: String::from("custom"), colors: palette })
+    }
+
+    // A palette name, or colours for a custom palette
+    pub fn from_name_or_colors(text: &str) -> Result<Palette, String> {
+        match Palette::from_name(text) {
+            Some(palette) => Ok(palette),
+            None if text.contains('#') => Palette::parse(text),
+            None => Err(format!("{} isn't one of the palettes {} or a list of colours like #000000,#FFFFFF", text, Palette::NAMES.join(", "))),
+        }
+    }
+
+    // The colour of a pixel with this display value
+    pub fn color(&self, pixel: u8) -> [u8; 3] {
+        self.colors[(pixel & 3) as usize]
+    }
+}
+
+impl Default for Palette {
+    fn default() -> Self {
+        Palette::from_name("default").unwrap()
+    }
+}
+
+fn parse_color(text: &str) -> Result<[u8; 3], String> {
+    let hex = text.strip_prefix('#').unwrap_or(text);
+    let value = match u32::from_str_radix(hex, 16) {
+        Ok(value) if hex.len() == 6 => value,
+        _ => return Err(format!("{} isn't a colour like #40FF60", text)),
+    };
+    Ok([(value >> 16) as u8, (value >> 8) as u8, value as u8])
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_custom_palettes() {
+        let palette = Palette::parse("#000000, #FFFFFF,#ff0000").unwrap();
+        assert_eq!(palette.colors, [[0, 0, 0], [255, 255, 255], [255, 0, 0], [255, 255, 255]]);
+        assert_eq!(palette.color(2), [255, 0, 0]);
+        assert_eq!(Palette::from_name_or_colors("amber").unwrap().name, "amber");
+        assert!(Palette::parse("#000000").is_err());
+        assert!(Palette::from_name_or_colors("#12345G,#000000").is_err());
+        assert!(Palette::from_name_or_colors("purple").is_err());
+    }
+
+}
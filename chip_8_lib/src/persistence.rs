@@ -0,0 +1,50 @@
+// CHIP-8 games erase sprites by XORing them off and drawing them again, so anything that moves
+// flickers. A persistence filter hides this by blending each frame with the ones before it,
+// leaving the emulated display alone.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Persistence {
+    #[default]
+    Off,
+
+    // Shows a pixel if it's lit in this frame or the one before, so a sprite that's erased
+    // and redrawn a frame later doesn't disappear in between
+    Deflicker,
+
+    // Lit pixels light up at once and fade out like the phosphor on a CRT, keeping this much
+    // of their brightness each frame (between 0 and 1)
+    Phosphor(f32),
+}
+
+impl Persistence {
+    pub const DEFAULT_DECAY: f32 = 0.5;
+
+    // Parses "off", "deflicker", "phosphor" or "phosphor:DECAY"
+    pub fn parse(text: &str) -> Result<Persistence, String> {
+        match text.trim() {
+            "off" => Ok(Persistence::Off),
+            "deflicker" => Ok(Persistence::Deflicker),
+            "phosphor" => Ok(Persistence::Phosphor(Persistence::DEFAULT_DECAY)),
+            text => match text.strip_prefix("phosphor:").map(str::parse::<f32>) {
+                Some(Ok(decay)) if (0.0..1.0).contains(&decay) => Ok(Persistence::Phosphor(decay)),
+                Some(_) => Err(format!("{} needs a decay of at least 0 and less than 1, like phosphor:0.5", text)),
+                None => Err(format!("unknown persistence `{}`, expected off, deflicker or phosphor[:DECAY]", text)),
+            },
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_filters() {
+        assert_eq!(Persistence::parse("deflicker"), Ok(Persistence::Deflicker));
+        assert_eq!(Persistence::parse("phosphor"), Ok(Persistence::Phosphor(0.5)));
+        assert_eq!(Persistence::parse("phosphor:0.8"), Ok(Persistence::Phosphor(0.8)));
+        assert!(Persistence::parse("phosphor:1").is_err());
+        assert!(Persistence::parse("blur").is_err());
+    }
+
+}
@@ -0,0 +1,84 @@
+use crate::checksum;
+
+const SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1A, b'\n'];
+
+// Zlib stored blocks can't be longer than this
+const MAX_BLOCK: usize = 0xFFFF;
+
+// Encodes an RGBA image as a PNG. The image data isn't compressed, deflate's stored blocks keep
+// this short and screenshots are small anyway.
+pub fn encode(width: usize, height: usize, rgba: &[u8]) -> Vec<u8> {
+    assert_eq!(rgba.len(), width * height * 4, "RGBA data doesn't match the image size");
+
+    let mut png = SIGNATURE.to_vec();
+
+    let mut header = vec![];
+    header.extend_from_slice(&(width as u32).to_be_bytes());
+    header.extend_from_slice(&(height as u32).to_be_bytes());
+    // 8 bits per channel, RGBA, deflate, no filtering, not interlaced
+    header.extend_from_slice(&[8, 6, 0, 0, 0]);
+    chunk(&mut png, b"IHDR", &header);
+
+    // Each row starts with its filter type, 0 for none
+    let mut raw = Vec::with_capacity((width * 4 + 1) * height);
+    for row in rgba.chunks(width * 4) {
+        raw.push(0);
+        raw.extend_from_slice(row);
+    }
+    chunk(&mut png, b"IDAT", &zlib_stored(&raw));
+    chunk(&mut png, b"IEND", &[]);
+    png
+}
+
+fn chunk(png: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    png.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    let start = png.len();
+    png.extend_from_slice(kind);
+    png.extend_from_slice(data);
+    let crc = checksum::crc32(&png[start..]);
+    png.extend_from_slice(&crc.to_be_bytes());
+}
+
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    let mut zlib = vec![0x78, 0x01];
+    let blocks: Vec<&[u8]> = if data.is_empty() { vec![data] } else { data.chunks(MAX_BLOCK).collect() };
+    for (idx, block) in blocks.iter().enumerate() {
+        zlib.push(if idx == blocks.len() - 1 { 1 } else { 0 });
+        let len = block.len() as u16;
+        zlib.extend_from_slice(&len.to_le_bytes());
+        zlib.extend_from_slice(&(!len).to_le_bytes());
+        zlib.extend_from_slice(block);
+    }
+    zlib.extend_from_slice(&adler32(data).to_be_bytes());
+    zlib
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for byte in data {
+        a = (a + *byte as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+    (b << 16) | a
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn writes_a_valid_png() {
+        assert_eq!(adler32(b"Wikipedia"), 0x11E6_0398);
+
+        let png = encode(2, 1, &[255, 0, 0, 255, 0, 0, 255, 255]);
+        assert_eq!(png[..8], SIGNATURE);
+        assert_eq!(&png[12..16], b"IHDR");
+        assert_eq!(png[16..24], [0, 0, 0, 2, 0, 0, 0, 1]);
+        assert_eq!(&png[png.len() - 8..png.len() - 4], b"IEND");
+        // IDAT holds the zlib header, a final stored block with the 9 byte row, and the checksum
+        assert_eq!(png[33..37], [0, 0, 0, 2 + 5 + 9 + 4]);
+        assert_eq!(png[41..48], [0x78, 0x01, 1, 9, 0, !9, 0xFF]);
+    }
+
+}
@@ -0,0 +1,240 @@
+use std::collections::BTreeMap;
+
+use crate::cpu::PROGRAM_START;
+use crate::disassembler;
+use crate::json;
+
+const MEMORY_SIZE: usize = 4096;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct SubroutineProfile {
+    // Number of times the subroutine was called
+    pub calls: u64,
+
+    // Instructions executed in the subroutine itself
+    pub flat: u64,
+
+    // Instructions executed between the CALL and its RET, including other subroutines it called
+    pub inclusive: u64,
+}
+
+// Counts instructions executed per address and per subroutine.
+// Attach one to Cpu::profiler and every emulate_cycle() call is recorded.
+pub struct Profiler {
+    // Number of times the instruction at each address was executed
+    pub hits: Vec<u64>,
+
+    // The last opcode executed at each address, kept for the disassembly in reports
+    pub opcodes: Vec<u16>,
+
+    // Subroutine entry address -> profile. Code outside of any subroutine is
+    // attributed to the program entry point (0x200).
+    pub subroutines: BTreeMap<u16, SubroutineProfile>,
+
+    // Total instructions executed
+    pub total: u64,
+
+    // Entry addresses of the subroutines currently being executed and the value of total
+    // when each of them was called
+    call_stack: Vec<(u16, u64)>,
+}
+
+impl Default for Profiler {
+    fn default() -> Self {
+        Profiler::new()
+    }
+}
+
+impl Profiler {
+    pub fn new() -> Profiler {
+        Profiler {
+            hits: vec![0; MEMORY_SIZE],
+            opcodes: vec![0; MEMORY_SIZE],
+            subroutines: BTreeMap::new(),
+            total: 0,
+            call_stack: vec![],
+        }
+    }
+
+    // Called by the Cpu after it has executed the opcode at pc
+    pub fn record(&mut self, pc: u16, opcode: u16) {
+        let address = pc as usize % MEMORY_SIZE;
+        self.hits[address] += 1;
+        self.opcodes[address] = opcode;
+        self.total += 1;
+
+        let current = self.current_subroutine();
+        self.subroutines.entry(current).or_default().flat += 1;
+
+        match opcode {
+            0x2000..=0x2FFF => {
+                // CALL counts towards the caller, everything from the first instruction
+                // of the subroutine up to and including its RET counts towards the callee.
+                let target = opcode & 0x0FFF;
+                self.subroutines.entry(target).or_default().calls += 1;
+                self.call_stack.push((target, self.total));
+            }
+            0x00EE => {
+                if let Some((entry, called_at)) = self.call_stack.pop() {
+                    // Recursive calls would otherwise be counted more than once
+                    if !self.call_stack.iter().any(|(e, _)| *e == entry) {
+                        self.subroutines.entry(entry).or_default().inclusive += self.total - called_at;
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn current_subroutine(&self) -> u16 {
+        match self.call_stack.last() {
+            Some((entry, _)) => *entry,
+            None => PROGRAM_START as u16,
+        }
+    }
+
+    // Subroutine profiles, with the inclusive counts of calls that haven't returned yet
+    // (and of the top level code) filled in up to now. Sorted by inclusive count, highest first.
+    pub fn subroutine_profile(&self) -> Vec<(u16, SubroutineProfile)> {
+        let mut subroutines = self.subroutines.clone();
+        subroutines.entry(PROGRAM_START as u16).or_default().inclusive = self.total;
+        for (idx, (entry, called_at)) in self.call_stack.iter().enumerate() {
+            if !self.call_stack[..idx].iter().any(|(e, _)| e == entry) {
+                subroutines.entry(*entry).or_default().inclusive += self.total - called_at;
+            }
+        }
+
+        let mut profile: Vec<(u16, SubroutineProfile)> = subroutines.into_iter().collect();
+        profile.sort_by(|a, b| b.1.inclusive.cmp(&a.1.inclusive).then(a.0.cmp(&b.0)));
+        profile
+    }
+
+    // The most executed addresses as (address, opcode, hits), highest first
+    pub fn hotspots(&self, count: usize) -> Vec<(u16, u16, u64)> {
+        let mut hotspots: Vec<(u16, u16, u64)> = self
+            .hits
+            .iter()
+            .enumerate()
+            .filter(|(_, hits)| **hits > 0)
+            .map(|(address, hits)| (address as u16, self.opcodes[address], *hits))
+            .collect();
+        hotspots.sort_by(|a, b| b.2.cmp(&a.2).then(a.0.cmp(&b.0)));
+        hotspots.truncate(count);
+        hotspots
+    }
+
+    pub fn report(&self, count: usize) -> String {
+        let mut lines = vec![format!("Instructions executed: {}", self.total), String::new()];
+
+        lines.push(String::from("Hottest addresses:"));
+        lines.push(format!("  {:<7} {:>10} {:>7}  {}", "address", "hits", "%", "instruction"));
+        for (address, opcode, hits) in self.hotspots(count) {
+            lines.push(format!(
+                "  0x{:03X}   {:>10} {:>6.2}%  {}",
+                address,
+                hits,
+                percent(hits, self.total),
+                disassembler::disassemble(opcode)
+            ));
+        }
+
+        lines.push(String::new());
+        lines.push(String::from("Subroutines:"));
+        lines.push(format!(
+            "  {:<7} {:>8} {:>10} {:>7} {:>10} {:>7}",
+            "address", "calls", "flat", "%", "inclusive", "%"
+        ));
+        for (entry, profile) in self.subroutine_profile() {
+            lines.push(format!(
+                "  0x{:03X}   {:>8} {:>10} {:>6.2}% {:>10} {:>6.2}%",
+                entry,
+                profile.calls,
+                profile.flat,
+                percent(profile.flat, self.total),
+                profile.inclusive,
+                percent(profile.inclusive, self.total)
+            ));
+        }
+
+        lines.join("\n")
+    }
+
+    pub fn to_json(&self) -> String {
+        let addresses: Vec<String> = self
+            .hotspots(MEMORY_SIZE)
+            .iter()
+            .map(|(address, opcode, hits)| {
+                format!(
+                    "{{\"address\":{},\"opcode\":{},\"instruction\":{},\"hits\":{}}}",
+                    address,
+                    opcode,
+                    json::string(disassembler::disassemble(*opcode).trim()),
+                    hits
+                )
+            })
+            .collect();
+
+        let subroutines: Vec<String> = self
+            .subroutine_profile()
+            .iter()
+            .map(|(entry, profile)| {
+                format!(
+                    "{{\"address\":{},\"calls\":{},\"flat\":{},\"inclusive\":{}}}",
+                    entry, profile.calls, profile.flat, profile.inclusive
+                )
+            })
+            .collect();
+
+        format!(
+            "{{\"total\":{},\"addresses\":[{}],\"subroutines\":[{}]}}",
+            self.total,
+            addresses.join(","),
+            subroutines.join(",")
+        )
+    }
+}
+
+fn percent(count: u64, total: u64) -> f64 {
+    if total == 0 {
+        0.0
+    } else {
+        count as f64 * 100.0 / total as f64
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cpu::Cpu;
+
+    #[test]
+    fn attributes_calls_to_subroutines() {
+        let mut profiler = Profiler::new();
+        profiler.record(0x200, 0x2300); // CALL 0x300
+        profiler.record(0x300, 0x2400); // CALL 0x400
+        profiler.record(0x400, 0x6001);
+        profiler.record(0x402, 0x00EE);
+        profiler.record(0x302, 0x00EE);
+        profiler.record(0x202, 0x1202);
+
+        let profile: BTreeMap<u16, SubroutineProfile> = profiler.subroutine_profile().into_iter().collect();
+        assert_eq!(profile[&0x200], SubroutineProfile { calls: 0, flat: 2, inclusive: 6 });
+        assert_eq!(profile[&0x300], SubroutineProfile { calls: 1, flat: 2, inclusive: 4 });
+        assert_eq!(profile[&0x400], SubroutineProfile { calls: 1, flat: 2, inclusive: 2 });
+        assert_eq!(profiler.hotspots(1), vec![(0x200, 0x2300, 1)]);
+    }
+
+    #[test]
+    fn calls_that_overflow_the_stack_are_not_entered() {
+        let mut cpu = Cpu::new();
+        cpu.load_game(vec![0x22, 0x00]); // CALL 0x200, forever
+        cpu.profiler = Some(Profiler::new());
+        while cpu.emulate_cycle().is_ok() {}
+
+        let profiler = cpu.profiler.unwrap();
+        assert_eq!(profiler.call_stack.len(), cpu.sp as usize);
+        assert_eq!(profiler.total, cpu.cycle - 1);
+    }
+
+}
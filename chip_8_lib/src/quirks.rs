@@ -0,0 +1,74 @@
+// Behaviours that differ between CHIP-8 interpreters. Games are written against one of them,
+// so running a game with the wrong set of quirks can make it glitch or crash.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Quirks {
+    // 8xy6 and 8xyE shift Vy and store the result in Vx, instead of shifting Vx in place
+    pub shift_uses_vy: bool,
+
+    // Fx55 and Fx65 leave I pointing at the address after the last register stored or loaded
+    pub load_store_increments_i: bool,
+
+    // Bnnn jumps to nnn plus Vx, where x is the highest nibble of nnn, instead of nnn plus V0
+    pub jump_uses_vx: bool,
+
+    // 8xy1, 8xy2 and 8xy3 set VF to 0
+    pub logic_resets_vf: bool,
+
+    // Sprites are cut off at the edges of the screen instead of wrapping around
+    pub clip_sprites: bool,
+
+    // Fx0A waits for the key to be released again before storing it, instead of storing it as
+    // soon as it's pressed
+    pub wait_for_release: bool,
+}
+
+impl Quirks {
+    // How this emulator has always behaved
+    pub const DEFAULT: Quirks = Quirks {
+        shift_uses_vy: false,
+        load_store_increments_i: false,
+        jump_uses_vx: false,
+        logic_resets_vf: false,
+        clip_sprites: false,
+        wait_for_release: false,
+    };
+
+    // The original interpreter on the RCA COSMAC VIP
+    pub const COSMAC_VIP: Quirks = Quirks {
+        shift_uses_vy: true,
+        load_store_increments_i: true,
+        jump_uses_vx: false,
+        logic_resets_vf: true,
+        clip_sprites: true,
+        wait_for_release: true,
+    };
+
+    // SUPER-CHIP 1.1 on the HP48 calculators
+    pub const SUPER_CHIP: Quirks = Quirks {
+        shift_uses_vy: false,
+        load_store_increments_i: false,
+        jump_uses_vx: true,
+        logic_resets_vf: false,
+        clip_sprites: true,
+        wait_for_release: false,
+    };
+
+    pub const PROFILES: [(&'static str, Quirks); 3] = [
+        ("default", Quirks::DEFAULT),
+        ("vip", Quirks::COSMAC_VIP),
+        ("schip", Quirks::SUPER_CHIP),
+    ];
+
+    pub fn from_name(name: &str) -> Option<Quirks> {
+        Quirks::PROFILES
+            .iter()
+            .find(|(profile, _)| *profile == name)
+            .map(|(_, quirks)| *quirks)
+    }
+}
+
+impl Default for Quirks {
+    fn default() -> Self {
+        Quirks::DEFAULT
+    }
+}
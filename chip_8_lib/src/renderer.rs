@@ -0,0 +1,201 @@
+use crate::crt::Crt;
+use crate::dirty_rect::DirtyRect;
+use crate::palette::Palette;
+use crate::persistence::Persistence;
+
+const WIDTH: usize = 64;
+const HEIGHT: usize = 32;
+
+// The largest scale, 1024x512 pixels. Screenshots with CRT effects need the room, anything
+// bigger only makes huge images.
+pub const MAX_SCALE: usize = 16;
+
+// Checks a scale is between 1 and MAX_SCALE, so frontends can reject one rather than draw at a
+// different size than was asked for
+pub fn check_scale(scale: usize) -> Result<usize, String> {
+    if (1..=MAX_SCALE).contains(&scale) {
+        Ok(scale)
+    } else {
+        Err(format!("scale {} isn't between 1 and {}", scale, MAX_SCALE))
+    }
+}
+
+// Turns the display into an image, drawing every display pixel as a scale x scale square in
+// its palette colour, after the persistence filter and with any CRT effects on top. The
+// browser, PNG screenshots and GIF recordings all draw through this so they look the same.
+//
+// Call update() once a frame with the display, then render() or render_rect() to draw it.
+// The scale should have been through check_scale().
+#[derive(Clone, Debug, PartialEq)]
+pub struct Renderer {
+    pub palette: Palette,
+    pub scale: usize,
+    pub persistence: Persistence,
+    pub crt: Crt,
+
+    // The display at the last update()
+    previous: Vec<u8>,
+
+    // The colour of each display pixel after the persistence filter. Kept as floats so
+    // phosphor fades out smoothly instead of getting stuck on a rounded value.
+    glow: Vec<[f32; 3]>,
+
+    // The glow rounded to the colours that get drawn
+    colors: Vec<[u8; 3]>,
+}
+
+impl Renderer {
+    pub fn new(palette: Palette, scale: usize) -> Renderer {
+        let background = palette.color(0);
+        Renderer {
+            palette,
+            scale,
+            persistence: Persistence::Off,
+            crt: Crt::default(),
+            previous: vec![0; WIDTH * HEIGHT],
+            glow: vec![to_float(background); WIDTH * HEIGHT],
+            colors: vec![background; WIDTH * HEIGHT],
+        }
+    }
+
+    // Size of the image in pixels
+    pub fn width(&self) -> usize {
+        WIDTH * self.scale
+    }
+
+    pub fn height(&self) -> usize {
+        HEIGHT * self.scale
+    }
+
+    // Works out the colour of every pixel for a new frame of the display. Returns the area
+    // of the display whose colours changed, if any.
+    pub fn update(&mut self, display: &[u8]) -> Option<DirtyRect> {
+        let background = to_float(self.palette.color(0));
+        let mut changed: Option<DirtyRect> = None;
+        for (idx, pixel) in display.iter().enumerate() {
+            let glow = match self.persistence {
+                Persistence::Off => to_float(self.palette.color(*pixel)),
+                Persistence::Deflicker => to_float(self.palette.color(*pixel | self.previous[idx])),
+                Persistence::Phosphor(_) if *pixel != 0 => to_float(self.palette.color(*pixel)),
+                Persistence::Phosphor(decay) => {
+                    let mut faded = self.glow[idx];
+                    for (channel, background) in faded.iter_mut().zip(background.iter()) {
+                        *channel = background + (*channel - background) * decay;
+                    }
+                    faded
+                }
+            };
+            self.glow[idx] = glow;
+
+            let color = [glow[0].round() as u8, glow[1].round() as u8, glow[2].round() as u8];
+            if color != self.colors[idx] {
+                self.colors[idx] = color;
+                let pixel = DirtyRect::pixel(idx % WIDTH, idx / WIDTH);
+                changed = Some(changed.map_or(pixel, |changed| changed.union(&pixel)));
+            }
+        }
+        self.previous.copy_from_slice(display);
+        changed
+    }
+
+    // The whole image as RGBA
+    pub fn render(&self) -> Vec<u8> {
+        let mut rgba = vec![0; self.width() * self.height() * 4];
+        self.render_rect(&mut rgba, Renderer::full_screen());
+        rgba
+    }
+
+    // Redraws the part of an RGBA image from render() covering this area of the display.
+    // Returns the area of the image that was redrawn, which is all of it with CRT effects on
+    // since bloom and curvature spread pixels around.
+    pub fn render_rect(&self, rgba: &mut [u8], rect: DirtyRect) -> DirtyRect {
+        if !self.crt.is_off() {
+            self.crt.render(&self.colors, self.palette.color(0), self.scale, rgba);
+            return DirtyRect { x: 0, y: 0, width: self.width(), height: self.height() };
+        }
+        for y in rect.y..rect.y + rect.height {
+            for x in rect.x..rect.x + rect.width {
+                let [r, g, b] = self.colors[x + y * WIDTH];
+                for row in y * self.scale..(y + 1) * self.scale {
+                    let start = (row * self.width() + x * self.scale) * 4;
+                    for pixel in rgba[start..start + self.scale * 4].chunks_mut(4) {
+                        pixel.copy_from_slice(&[r, g, b, 255]);
+                    }
+                }
+            }
+        }
+        DirtyRect {
+            x: rect.x * self.scale,
+            y: rect.y * self.scale,
+            width: rect.width * self.scale,
+            height: rect.height * self.scale,
+        }
+    }
+
+    fn full_screen() -> DirtyRect {
+        DirtyRect { x: 0, y: 0, width: WIDTH, height: HEIGHT }
+    }
+}
+
+impl Default for Renderer {
+    fn default() -> Self {
+        Renderer::new(Palette::default(), 1)
+    }
+}
+
+fn to_float(color: [u8; 3]) -> [f32; 3] {
+    [color[0] as f32, color[1] as f32, color[2] as f32]
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scales_pixels_in_palette_colours() {
+        let mut display = [0; WIDTH * HEIGHT];
+        display[1] = 1;
+        display[WIDTH] = 3;
+        let mut renderer = Renderer::new(Palette::from_name("lcd").unwrap(), 2);
+        assert_eq!(renderer.update(&display), Some(DirtyRect { x: 0, y: 0, width: 2, height: 2 }));
+        assert_eq!(renderer.update(&display), None);
+        let rgba = renderer.render();
+        assert_eq!((renderer.width(), renderer.height(), rgba.len()), (128, 64, 128 * 64 * 4));
+
+        let pixel = |x: usize, y: usize| &rgba[(x + y * 128) * 4..(x + y * 128) * 4 + 4];
+        assert_eq!(pixel(1, 1), [155, 188, 15, 255]);
+        assert_eq!(pixel(2, 0), [15, 56, 15, 255]);
+        assert_eq!(pixel(3, 1), [15, 56, 15, 255]);
+        assert_eq!(pixel(1, 3), [139, 172, 15, 255]);
+
+        assert_eq!(check_scale(MAX_SCALE), Ok(MAX_SCALE));
+        assert!(check_scale(0).is_err() && check_scale(MAX_SCALE + 1).is_err());
+    }
+
+    #[test]
+    fn persistence_blends_frames() {
+        let palette = Palette::parse("#000000,#C8C8C8").unwrap();
+        let mut lit = [0; WIDTH * HEIGHT];
+        lit[0] = 1;
+        let blank = [0; WIDTH * HEIGHT];
+
+        let mut renderer = Renderer::new(palette.clone(), 1);
+        renderer.persistence = Persistence::Deflicker;
+        renderer.update(&lit);
+        renderer.update(&blank);
+        assert_eq!(renderer.render()[..4], [200, 200, 200, 255]);
+        renderer.update(&blank);
+        assert_eq!(renderer.render()[..4], [0, 0, 0, 255]);
+
+        let mut renderer = Renderer::new(palette, 1);
+        renderer.persistence = Persistence::Phosphor(0.5);
+        renderer.update(&lit);
+        let fades: Vec<u8> = (0..3).map(|_| {
+            renderer.update(&blank);
+            renderer.render()[0]
+        }).collect();
+        assert_eq!(fades, [100, 50, 25]);
+    }
+
+}
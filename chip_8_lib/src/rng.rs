@@ -0,0 +1,37 @@
+// Seedable pseudo random number generator for Cxkk, so runs can be reproduced exactly.
+// This is xorshift64*, which is plenty for games and needs no dependencies.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Rng {
+    pub state: u64,
+}
+
+impl Rng {
+    pub const fn new(seed: u64) -> Rng {
+        // xorshift gets stuck at zero, so zero is mapped to an arbitrary non zero state
+        let state = if seed == 0 { 0x9E37_79B9_7F4A_7C15 } else { seed };
+        Rng { state }
+    }
+
+    pub fn next_u8(&mut self) -> u8 {
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        (self.state.wrapping_mul(0x2545_F491_4F6C_DD1D) >> 56) as u8
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn same_seed_same_sequence() {
+        let mut a = Rng::new(42);
+        let mut b = Rng::new(42);
+        let sequence: Vec<u8> = (0..16).map(|_| a.next_u8()).collect();
+        assert_eq!(sequence, (0..16).map(|_| b.next_u8()).collect::<Vec<u8>>());
+        assert!(sequence.iter().any(|value| *value != sequence[0]));
+    }
+
+}
@@ -0,0 +1,47 @@
+// Known ROMs, keyed by the CRC-32 of the ROM file.
+// Currently this is the public domain games pack bundled in chip_8_wasm/static/roms.
+
+pub struct RomEntry {
+    pub crc32: u32,
+
+    // File name in the bundled roms directory
+    pub name: &'static str,
+
+    pub title: &'static str,
+
+    // Keys to map on top of the chosen keypad layout, as (host key, CHIP-8 key), so games
+    // that move with 2, 4, 6 and 8 can be played with the arrow keys
+    pub keys: &'static [(&'static str, u8)],
+}
+
+const ARROWS_AND_SPACE: [(&str, u8); 5] = [("ArrowUp", 0x2), ("ArrowLeft", 0x4), ("ArrowRight", 0x6), ("ArrowDown", 0x8), ("Space", 0x5)];
+
+pub const ROMS: [RomEntry; 23] = [
+    RomEntry { crc32: 0x4E86_93F1, name: "15PUZZLE", title: "15 Puzzle", keys: &[] },
+    RomEntry { crc32: 0x9D30_7E90, name: "BLINKY", title: "Blinky", keys: &[] },
+    RomEntry { crc32: 0xD106_C808, name: "BLITZ", title: "Blitz", keys: &[] },
+    RomEntry { crc32: 0xAAA4_4D0B, name: "BRIX", title: "Brix", keys: &ARROWS_AND_SPACE },
+    RomEntry { crc32: 0x9858_889B, name: "CONNECT4", title: "Connect 4", keys: &[] },
+    RomEntry { crc32: 0x432E_2FE1, name: "GUESS", title: "Guess", keys: &[] },
+    RomEntry { crc32: 0x6186_1AE5, name: "HIDDEN", title: "Hidden", keys: &[] },
+    RomEntry { crc32: 0xEAD6_25B8, name: "INVADERS", title: "Space Invaders", keys: &ARROWS_AND_SPACE },
+    RomEntry { crc32: 0x08A9_3FAE, name: "KALEID", title: "Kaleidoscope", keys: &[] },
+    RomEntry { crc32: 0x37A6_58A2, name: "MAZE", title: "Maze", keys: &[] },
+    RomEntry { crc32: 0x1096_C3D5, name: "MERLIN", title: "Merlin", keys: &[] },
+    RomEntry { crc32: 0x6E48_5C29, name: "MISSILE", title: "Missile Command", keys: &[] },
+    RomEntry { crc32: 0x7D75_A857, name: "PONG", title: "Pong", keys: &[] },
+    RomEntry { crc32: 0x6997_0AD2, name: "PONG2", title: "Pong 2", keys: &[] },
+    RomEntry { crc32: 0x040C_A946, name: "PUZZLE", title: "Puzzle", keys: &[] },
+    RomEntry { crc32: 0x67E4_BF9C, name: "SYZYGY", title: "Syzygy", keys: &[] },
+    RomEntry { crc32: 0xA929_CB73, name: "TANK", title: "Tank", keys: &ARROWS_AND_SPACE },
+    RomEntry { crc32: 0x0CE7_0772, name: "TETRIS", title: "Tetris", keys: &[] },
+    RomEntry { crc32: 0x3A29_7A10, name: "TICTAC", title: "Tic-Tac-Toe", keys: &[] },
+    RomEntry { crc32: 0x3314_13E7, name: "UFO", title: "UFO", keys: &[] },
+    RomEntry { crc32: 0x608C_6AB0, name: "VBRIX", title: "Vertical Brix", keys: &[] },
+    RomEntry { crc32: 0x0DBF_7208, name: "VERS", title: "Vers", keys: &[] },
+    RomEntry { crc32: 0xB269_6048, name: "WIPEOFF", title: "Wipe Off", keys: &[] },
+];
+
+pub fn lookup(crc32: u32) -> Option<&'static RomEntry> {
+    ROMS.iter().find(|entry| entry.crc32 == crc32)
+}
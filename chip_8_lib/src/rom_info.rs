@@ -0,0 +1,286 @@
+use std::collections::BTreeMap;
+use std::fmt;
+
+use crate::checksum;
+use crate::cpu::{Cpu, PROGRAM_START};
+use crate::disassembler;
+use crate::rom_database::{self, RomEntry};
+
+const MEMORY_SIZE: usize = 4096;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Platform {
+    Chip8,
+    SuperChip,
+    XoChip,
+}
+
+impl fmt::Display for Platform {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Platform::Chip8 => write!(f, "CHIP-8"),
+            Platform::SuperChip => write!(f, "SUPER-CHIP"),
+            Platform::XoChip => write!(f, "XO-CHIP"),
+        }
+    }
+}
+
+// The oldest platform an opcode pattern (see disassembler::opcode_pattern) is available on
+fn platform_of(pattern: &str) -> Platform {
+    match pattern {
+        "00Dn" | "5xy2" | "5xy3" | "F000 nnnn" | "Fn01" | "F002" | "Fx3A" => Platform::XoChip,
+        "00Cn" | "00FB" | "00FC" | "00FD" | "00FE" | "00FF" | "Dxy0" | "Fx30" | "Fx75" | "Fx85" => {
+            Platform::SuperChip
+        }
+        _ => Platform::Chip8,
+    }
+}
+
+// Static report about a ROM, computed by walking the code reachable from the entry point
+// without running it.
+pub struct RomInfo {
+    pub size: usize,
+    pub crc32: u32,
+    pub database_entry: Option<&'static RomEntry>,
+    pub platform: Platform,
+
+    // Opcode pattern -> number of reachable instructions using it
+    pub opcode_histogram: BTreeMap<&'static str, usize>,
+
+    // Reachable code, as (start, end) address ranges with an exclusive end
+    pub code_ranges: Vec<(u16, u16)>,
+
+    // Memory the program points I at and reads or writes, as (start, end) address ranges
+    pub data_ranges: Vec<(u16, u16)>,
+
+    // True if Fx33 or Fx55 store into memory that is also reachable code
+    pub writes_to_code: bool,
+
+    // Reachable instructions emulate_cycle() can't execute, as (address, opcode)
+    pub unsupported_opcodes: Vec<(u16, u16)>,
+}
+
+impl RomInfo {
+    pub fn analyze(rom: &[u8]) -> RomInfo {
+        let crc32 = checksum::crc32(rom);
+        let rom_end = (PROGRAM_START + rom.len()).min(MEMORY_SIZE);
+
+        let mut memory = [0u8; MEMORY_SIZE];
+        memory[PROGRAM_START..rom_end].copy_from_slice(&rom[..rom_end - PROGRAM_START]);
+
+        let mut visited = [false; MEMORY_SIZE];
+        let mut code = [false; MEMORY_SIZE];
+        let mut opcode_histogram = BTreeMap::new();
+        let mut data_ranges = vec![];
+        let mut write_ranges = vec![];
+        let mut unsupported_opcodes = vec![];
+        let mut platform = Platform::Chip8;
+
+        let mut worklist = vec![PROGRAM_START];
+        while let Some(start) = worklist.pop() {
+            let mut address = start;
+
+            // The value of I, if it was set by an earlier instruction in this straight line run
+            let mut i: Option<usize> = None;
+
+            while address >= PROGRAM_START && address + 1 < rom_end && !visited[address] {
+                visited[address] = true;
+                code[address] = true;
+                code[address + 1] = true;
+
+                let opcode = (memory[address] as u16) << 8 | memory[address + 1] as u16;
+                let pattern = disassembler::opcode_pattern(opcode);
+                *opcode_histogram.entry(pattern).or_insert(0) += 1;
+                platform = platform.max(platform_of(pattern));
+                if !Cpu::supports_opcode(opcode) {
+                    unsupported_opcodes.push((address as u16, opcode));
+                }
+
+                let x = ((opcode & 0x0F00) >> 8) as usize;
+                let nnn = (opcode & 0x0FFF) as usize;
+                let mut next = Some(address + 2);
+                match pattern {
+                    "1nnn" => {
+                        worklist.push(nnn);
+                        next = None;
+                    }
+                    "2nnn" => worklist.push(nnn),
+                    "Bnnn" => {
+                        // The offset in V0 isn't known statically, only the base address is followed
+                        worklist.push(nnn);
+                        next = None;
+                    }
+                    "00EE" | "00FD" => next = None,
+                    "3xkk" | "4xkk" | "5xy0" | "5xy2" | "5xy3" | "9xy0" | "Ex9E" | "ExA1" => {
+                        worklist.push(address + 4);
+                    }
+                    "F000 nnnn" => {
+                        // XO-CHIP's long load is the only 4 byte instruction
+                        if address + 3 < rom_end {
+                            code[address + 2] = true;
+                            code[address + 3] = true;
+                            let long = (memory[address + 2] as usize) << 8 | memory[address + 3] as usize;
+                            data_ranges.push((long, long + 1));
+                        }
+                        next = Some(address + 4);
+                    }
+                    "Annn" => {
+                        i = Some(nnn);
+                        data_ranges.push((nnn, nnn + 1));
+                    }
+                    "Dxyn" => {
+                        if let Some(i) = i {
+                            data_ranges.push((i, i + (opcode & 0x000F) as usize));
+                        }
+                    }
+                    "Fx33" => {
+                        if let Some(i) = i {
+                            write_ranges.push((i, i + 3));
+                        }
+                    }
+                    "Fx55" => {
+                        if let Some(i) = i {
+                            write_ranges.push((i, i + x + 1));
+                        }
+                    }
+                    "Fx65" => {
+                        if let Some(i) = i {
+                            data_ranges.push((i, i + x + 1));
+                        }
+                    }
+                    "Fx1E" | "Fx29" => i = None,
+                    // Anything else that isn't a known instruction is most likely data
+                    "0nnn" | "5xy?" | "8xy?" | "9xy?" | "Ex??" | "Fx??" => next = None,
+                    _ => {}
+                }
+
+                match next {
+                    Some(next) => address = next,
+                    None => break,
+                }
+            }
+        }
+
+        let writes_to_code = write_ranges
+            .iter()
+            .any(|&(start, end)| (start..end.min(MEMORY_SIZE)).any(|address| code[address]));
+        data_ranges.extend(write_ranges);
+        unsupported_opcodes.sort();
+
+        RomInfo {
+            size: rom.len(),
+            crc32,
+            database_entry: rom_database::lookup(crc32),
+            platform,
+            opcode_histogram,
+            code_ranges: ranges_of(&code),
+            data_ranges: merge_ranges(data_ranges),
+            writes_to_code,
+            unsupported_opcodes,
+        }
+    }
+}
+
+// Contiguous runs of set addresses
+fn ranges_of(addresses: &[bool]) -> Vec<(u16, u16)> {
+    let mut ranges = vec![];
+    let mut start = None;
+    for (address, set) in addresses.iter().chain([false].iter()).enumerate() {
+        match (start, set) {
+            (None, true) => start = Some(address),
+            (Some(s), false) => {
+                ranges.push((s as u16, address as u16));
+                start = None;
+            }
+            _ => {}
+        }
+    }
+    ranges
+}
+
+// Sorts, clamps to memory and merges overlapping or adjacent ranges
+fn merge_ranges(mut ranges: Vec<(usize, usize)>) -> Vec<(u16, u16)> {
+    ranges.sort();
+    let mut merged: Vec<(u16, u16)> = vec![];
+    for (start, end) in ranges {
+        let (start, end) = (start.min(MEMORY_SIZE) as u16, end.min(MEMORY_SIZE) as u16);
+        if start >= end {
+            continue;
+        }
+        match merged.last_mut() {
+            Some(last) if start <= last.1 => last.1 = last.1.max(end),
+            _ => merged.push((start, end)),
+        }
+    }
+    merged
+}
+
+fn format_ranges(ranges: &[(u16, u16)]) -> String {
+    if ranges.is_empty() {
+        return String::from("none");
+    }
+    ranges
+        .iter()
+        .map(|(start, end)| format!("0x{:03X}-0x{:03X}", start, end - 1))
+        .collect::<Vec<String>>()
+        .join(", ")
+}
+
+impl fmt::Display for RomInfo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "Size:           {} bytes", self.size)?;
+        writeln!(f, "CRC-32:         {:08X}", self.crc32)?;
+        match self.database_entry {
+            Some(entry) => writeln!(f, "Database match: {} ({})", entry.title, entry.name)?,
+            None => writeln!(f, "Database match: none")?,
+        }
+        writeln!(f, "Platform:       {}", self.platform)?;
+        writeln!(f, "Code:           {}", format_ranges(&self.code_ranges))?;
+        writeln!(f, "Data:           {}", format_ranges(&self.data_ranges))?;
+        writeln!(f, "Writes to code: {}", if self.writes_to_code { "yes" } else { "no" })?;
+
+        writeln!(f, "Opcodes:")?;
+        for (pattern, count) in self.opcode_histogram.iter() {
+            writeln!(f, "  {:<10} {}", pattern, count)?;
+        }
+
+        if self.unsupported_opcodes.is_empty() {
+            write!(f, "Unsupported:    none")
+        } else {
+            write!(f, "Unsupported:")?;
+            for (address, opcode) in self.unsupported_opcodes.iter() {
+                write!(f, "\n  0x{:03X} {:04X} {}", address, opcode, disassembler::disassemble(*opcode))?;
+            }
+            Ok(())
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn follows_jumps_and_skips() {
+        let rom = [
+            0x30, 0x01, // 0x200 SE V0 1
+            0x12, 0x08, // 0x202 JP 0x208
+            0x00, 0xFD, // 0x204 SUPER-CHIP exit
+            0xFF, 0xFF, // 0x206 never reached
+            0xA2, 0x0E, // 0x208 LD I 0x20E
+            0xF1, 0x55, // 0x20A LD [I] V1
+            0x12, 0x0A, // 0x20C JP 0x20A
+            0x00, 0x00, // 0x20E data
+        ];
+        let info = RomInfo::analyze(&rom);
+
+        assert_eq!(info.platform, Platform::SuperChip);
+        assert_eq!(info.code_ranges, vec![(0x200, 0x206), (0x208, 0x20E)]);
+        assert_eq!(info.data_ranges, vec![(0x20E, 0x210)]);
+        assert!(!info.writes_to_code);
+        assert_eq!(info.unsupported_opcodes, vec![(0x204, 0x00FD)]);
+        assert_eq!(info.opcode_histogram.get("1nnn"), Some(&2));
+    }
+
+}
@@ -0,0 +1,199 @@
+use crate::cpu::Cpu;
+use crate::key_wait::KeyWait;
+use crate::rng::Rng;
+use crate::save_state_error::SaveStateError;
+
+const MAGIC: &[u8; 4] = b"C8ST";
+const VERSION: u8 = 3;
+
+// Save states are a fixed size binary snapshot of the machine:
+//
+//   magic "C8ST", version
+//   memory, pc, v, i, stack, sp, display, dt, st, keys
+//   key wait flag (0 = not waiting, 1 = Fx0A waiting) followed by its pc, register, held keys
+//   and pressed key (0xFF for none)
+//   rng flag (0 = operating system, 1 = seeded) followed by the rng state
+//   cycle
+//
+// Multi byte values are big endian. The quirks, profiler, coverage, instruction cache and
+// display change tracking aren't saved, they are configuration rather than machine state.
+// Neither are queued key events, they're input that hasn't reached the machine yet.
+impl Cpu {
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut data = Vec::with_capacity(state_len(self));
+        data.extend_from_slice(MAGIC);
+        data.push(VERSION);
+        data.extend_from_slice(&self.memory);
+        data.extend_from_slice(&self.pc.to_be_bytes());
+        data.extend_from_slice(&self.v);
+        data.extend_from_slice(&self.i.to_be_bytes());
+        for value in self.stack.iter() {
+            data.extend_from_slice(&value.to_be_bytes());
+        }
+        data.push(self.sp);
+        data.extend_from_slice(&self.display);
+        data.push(self.dt);
+        data.push(self.st);
+        data.extend(self.keys.iter().map(|key| *key as u8));
+        match self.key_wait {
+            Some(wait) => {
+                data.push(1);
+                data.extend_from_slice(&wait.pc.to_be_bytes());
+                data.push(wait.register as u8);
+                data.extend(wait.held.iter().map(|key| *key as u8));
+                data.push(wait.pressed.unwrap_or(0xFF));
+            }
+            None => data.extend_from_slice(&[0; 21]),
+        }
+        match self.rng {
+            Some(rng) => {
+                data.push(1);
+                data.extend_from_slice(&rng.state.to_be_bytes());
+            }
+            None => {
+                data.push(0);
+                data.extend_from_slice(&[0; 8]);
+            }
+        }
+        data.extend_from_slice(&self.cycle.to_be_bytes());
+        data
+    }
+
+    pub fn load_state(&mut self, data: &[u8]) -> Result<(), SaveStateError> {
+        if data.len() < MAGIC.len() + 1 || &data[..MAGIC.len()] != MAGIC {
+            return Err(SaveStateError { message: String::from("not a save state") });
+        }
+        if data[MAGIC.len()] != VERSION {
+            let message = format!("unsupported save state version {}", data[MAGIC.len()]);
+            return Err(SaveStateError { message });
+        }
+        if data.len() != state_len(self) {
+            let message = format!("save state is {} bytes, expected {}", data.len(), state_len(self));
+            return Err(SaveStateError { message });
+        }
+
+        // A program counter or stack pointer outside the machine would crash it later on
+        let pc_offset = MAGIC.len() + 1 + self.memory.len();
+        let pc = u16::from_be_bytes([data[pc_offset], data[pc_offset + 1]]);
+        let sp = data[pc_offset + 2 + 16 + 2 + self.stack.len() * 2];
+        if pc as usize >= self.memory.len() || sp as usize >= self.stack.len() {
+            return Err(SaveStateError { message: String::from("save state has an invalid pc or sp") });
+        }
+
+        let mut reader = Reader { data, position: MAGIC.len() + 1 };
+        let memory_len = self.memory.len();
+        self.memory.copy_from_slice(reader.bytes(memory_len));
+        self.pc = reader.u16();
+        self.v.copy_from_slice(reader.bytes(16));
+        self.i = reader.u16();
+        for value in self.stack.iter_mut() {
+            *value = reader.u16();
+        }
+        self.sp = reader.u8();
+        let display_len = self.display.len();
+        self.display.copy_from_slice(reader.bytes(display_len));
+        self.dt = reader.u8();
+        self.st = reader.u8();
+        for key in self.keys.iter_mut() {
+            *key = reader.u8() != 0;
+        }
+        let waiting = reader.u8() != 0;
+        let mut wait = KeyWait::new(reader.u16() & 0x0FFF, (reader.u8() & 0xF) as usize, &[false; 16]);
+        for key in wait.held.iter_mut() {
+            *key = reader.u8() != 0;
+        }
+        wait.pressed = Some(reader.u8()).filter(|key| *key < 16);
+        self.key_wait = if waiting { Some(wait) } else { None };
+        let seeded = reader.u8() != 0;
+        let mut rng_state = [0; 8];
+        rng_state.copy_from_slice(reader.bytes(8));
+        self.rng = if seeded {
+            Some(Rng { state: u64::from_be_bytes(rng_state) })
+        } else {
+            None
+        };
+        let mut cycle = [0; 8];
+        cycle.copy_from_slice(reader.bytes(8));
+        self.cycle = u64::from_be_bytes(cycle);
+
+        // Queued and logged key events belong to the timeline being left, their cycles mean
+        // nothing after the restored one
+        self.key_events.clear();
+        if let Some(log) = self.key_log.as_mut() {
+            log.clear();
+        }
+
+        self.invalidate_instruction_cache();
+        self.display_changed(Cpu::FULL_SCREEN);
+        Ok(())
+    }
+}
+
+fn state_len(cpu: &Cpu) -> usize {
+    MAGIC.len() + 1 + cpu.memory.len() + 2 + 16 + 2 + 16 * 2 + 1 + cpu.display.len() + 2 + 16 + 1 + 2 + 1 + 16 + 1 + 1 + 8 + 8
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, len: usize) -> &'a [u8] {
+        let bytes = &self.data[self.position..self.position + len];
+        self.position += len;
+        bytes
+    }
+
+    fn u8(&mut self) -> u8 {
+        self.bytes(1)[0]
+    }
+
+    fn u16(&mut self) -> u16 {
+        let bytes = self.bytes(2);
+        u16::from_be_bytes([bytes[0], bytes[1]])
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::key_event::KeyEvent;
+
+    #[test]
+    fn round_trips() {
+        let mut cpu = Cpu::new();
+        cpu.load_game(vec![0x60, 0x05, 0x22, 0x00]);
+        cpu.rng = Some(Rng::new(7));
+        cpu.emulate_cycle().unwrap();
+        cpu.emulate_cycle().unwrap();
+        cpu.display[100] = 1;
+        cpu.keys[3] = true;
+        cpu.dt = 9;
+
+        let state = cpu.save_state();
+        let mut restored = Cpu::new();
+        restored.load_state(&state).unwrap();
+        assert_eq!(restored.save_state(), state);
+        assert_eq!((restored.pc, restored.sp, restored.v[0]), (0x200, 1, 5));
+
+        // Key events queued before loading would otherwise fire at the wrong cycles
+        cpu.key_log = Some(vec![KeyEvent::press(1).at(1)]);
+        cpu.push_key_event(KeyEvent::press(2).at(50));
+        cpu.load_state(&state).unwrap();
+        assert!(cpu.key_events.is_empty());
+        assert_eq!(cpu.key_log, Some(vec![]));
+    }
+
+    #[test]
+    fn rejects_other_data() {
+        let mut cpu = Cpu::new();
+        assert!(cpu.load_state(b"PNG").is_err());
+
+        let mut state = cpu.save_state();
+        state.pop();
+        assert!(cpu.load_state(&state).is_err());
+    }
+
+}
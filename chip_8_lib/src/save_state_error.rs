@@ -0,0 +1,29 @@
+use std::error;
+use std::fmt;
+
+pub struct SaveStateError {
+    pub message: String,
+}
+
+impl fmt::Display for SaveStateError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl fmt::Debug for SaveStateError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "SaveStateError {{ message: {} }}",
+            self.message
+        )
+    }
+}
+
+impl error::Error for SaveStateError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        // Generic error, underlying cause isn't tracked.
+        None
+    }
+}
@@ -0,0 +1,18 @@
+// Helpers shared by the integration tests and the benchmarks, which include this file by path
+use std::fs;
+use std::path::PathBuf;
+
+// Every ROM in chip_8_wasm/static/roms with its file name, sorted by name
+pub fn bundled_roms() -> Vec<(String, Vec<u8>)> {
+    let dir = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("../chip_8_wasm/static/roms");
+    let mut roms: Vec<(String, Vec<u8>)> = fs::read_dir(dir)
+        .unwrap()
+        .map(|entry| {
+            let path = entry.unwrap().path();
+            let name = path.file_name().unwrap().to_string_lossy().to_string();
+            (name, fs::read(path).unwrap())
+        })
+        .collect();
+    roms.sort();
+    roms
+}
@@ -0,0 +1,283 @@
+// A reference table of what every opcode does. Each case sets up a machine, runs one
+// instruction and compares the whole machine against the expected state: anything the case
+// doesn't mention must be unchanged, apart from the program counter moving on to the next
+// instruction.
+use std::collections::BTreeSet;
+
+use chip_8_lib::cpu::Cpu;
+use chip_8_lib::disassembler;
+use chip_8_lib::quirks::Quirks;
+use chip_8_lib::rng::Rng;
+
+use Field::*;
+
+#[derive(Clone, Copy, Debug)]
+enum Field {
+    V(usize, u8),
+    I(u16),
+    Pc(u16),
+    Sp(u8),
+    Stack(usize, u16),
+    Mem(u16, u8),
+    Pixel(usize, usize, u8),
+    Dt(u8),
+    St(u8),
+    Key(usize),
+}
+
+struct Case {
+    name: &'static str,
+    opcode: u16,
+
+    // Quirk profiles the case applies to
+    profiles: &'static [&'static str],
+    before: &'static [Field],
+    after: &'static [Field],
+}
+
+const ALL: &[&str] = &["default", "vip", "schip"];
+
+const CASES: &[Case] = &[
+    Case { name: "CLS clears every pixel", opcode: 0x00E0, profiles: ALL,
+        before: &[Pixel(0, 0, 1), Pixel(63, 31, 1)], after: &[Pixel(0, 0, 0), Pixel(63, 31, 0)] },
+    Case { name: "RET pops the return address", opcode: 0x00EE, profiles: ALL,
+        before: &[Sp(1), Stack(1, 0x300)], after: &[Sp(0), Pc(0x302)] },
+    Case { name: "JP jumps", opcode: 0x1345, profiles: ALL,
+        before: &[], after: &[Pc(0x345)] },
+    Case { name: "CALL pushes the program counter", opcode: 0x2345, profiles: ALL,
+        before: &[], after: &[Sp(1), Stack(1, 0x200), Pc(0x345)] },
+    Case { name: "SE Vx, byte skips when equal", opcode: 0x3A12, profiles: ALL,
+        before: &[V(0xA, 0x12)], after: &[Pc(0x204)] },
+    Case { name: "SE Vx, byte doesn't skip when different", opcode: 0x3A12, profiles: ALL,
+        before: &[V(0xA, 0x13)], after: &[] },
+    Case { name: "SNE Vx, byte skips when different", opcode: 0x4A12, profiles: ALL,
+        before: &[V(0xA, 0x13)], after: &[Pc(0x204)] },
+    Case { name: "SNE Vx, byte doesn't skip when equal", opcode: 0x4A12, profiles: ALL,
+        before: &[V(0xA, 0x12)], after: &[] },
+    Case { name: "SE Vx, Vy skips when equal", opcode: 0x5120, profiles: ALL,
+        before: &[V(1, 7), V(2, 7)], after: &[Pc(0x204)] },
+    Case { name: "SE Vx, Vy doesn't skip when different", opcode: 0x5120, profiles: ALL,
+        before: &[V(1, 7), V(2, 8)], after: &[] },
+    Case { name: "LD Vx, byte", opcode: 0x6B42, profiles: ALL,
+        before: &[], after: &[V(0xB, 0x42)] },
+    Case { name: "ADD Vx, byte wraps without touching VF", opcode: 0x7002, profiles: ALL,
+        before: &[V(0, 0xFF), V(0xF, 5)], after: &[V(0, 1)] },
+    Case { name: "LD Vx, Vy", opcode: 0x8120, profiles: ALL,
+        before: &[V(2, 9)], after: &[V(1, 9)] },
+    Case { name: "OR leaves VF alone", opcode: 0x8121, profiles: &["default", "schip"],
+        before: &[V(1, 0x0C), V(2, 0x0A), V(0xF, 5)], after: &[V(1, 0x0E)] },
+    Case { name: "OR resets VF", opcode: 0x8121, profiles: &["vip"],
+        before: &[V(1, 0x0C), V(2, 0x0A), V(0xF, 5)], after: &[V(1, 0x0E), V(0xF, 0)] },
+    Case { name: "AND leaves VF alone", opcode: 0x8122, profiles: &["default", "schip"],
+        before: &[V(1, 0x0C), V(2, 0x0A), V(0xF, 5)], after: &[V(1, 0x08)] },
+    Case { name: "AND resets VF", opcode: 0x8122, profiles: &["vip"],
+        before: &[V(1, 0x0C), V(2, 0x0A), V(0xF, 5)], after: &[V(1, 0x08), V(0xF, 0)] },
+    Case { name: "XOR leaves VF alone", opcode: 0x8123, profiles: &["default", "schip"],
+        before: &[V(1, 0x0C), V(2, 0x0A), V(0xF, 5)], after: &[V(1, 0x06)] },
+    Case { name: "XOR resets VF", opcode: 0x8123, profiles: &["vip"],
+        before: &[V(1, 0x0C), V(2, 0x0A), V(0xF, 5)], after: &[V(1, 0x06), V(0xF, 0)] },
+    Case { name: "ADD Vx, Vy clears VF without a carry", opcode: 0x8124, profiles: ALL,
+        before: &[V(1, 0x10), V(2, 0x20), V(0xF, 1)], after: &[V(1, 0x30), V(0xF, 0)] },
+    Case { name: "ADD Vx, Vy sets VF on a carry", opcode: 0x8124, profiles: ALL,
+        before: &[V(1, 0xFF), V(2, 0x02)], after: &[V(1, 0x01), V(0xF, 1)] },
+    Case { name: "ADD VF, Vy keeps the carry in VF", opcode: 0x8F24, profiles: ALL,
+        before: &[V(0xF, 0xFF), V(2, 0x02)], after: &[V(0xF, 1)] },
+    Case { name: "SUB sets VF without a borrow", opcode: 0x8125, profiles: ALL,
+        before: &[V(1, 0x30), V(2, 0x10)], after: &[V(1, 0x20), V(0xF, 1)] },
+    Case { name: "SUB sets VF when equal", opcode: 0x8125, profiles: ALL,
+        before: &[V(1, 0x30), V(2, 0x30)], after: &[V(1, 0x00), V(0xF, 1)] },
+    Case { name: "SUB clears VF on a borrow", opcode: 0x8125, profiles: ALL,
+        before: &[V(1, 0x10), V(2, 0x30), V(0xF, 1)], after: &[V(1, 0xE0), V(0xF, 0)] },
+    Case { name: "SHR shifts Vx", opcode: 0x8126, profiles: &["default", "schip"],
+        before: &[V(1, 0x05), V(2, 0x40)], after: &[V(1, 0x02), V(0xF, 1)] },
+    Case { name: "SHR shifts Vy into Vx", opcode: 0x8126, profiles: &["vip"],
+        before: &[V(1, 0x05), V(2, 0x40), V(0xF, 1)], after: &[V(1, 0x20), V(0xF, 0)] },
+    Case { name: "SHR VF keeps the shifted out bit in VF", opcode: 0x8FF6, profiles: ALL,
+        before: &[V(0xF, 0x03)], after: &[V(0xF, 1)] },
+    Case { name: "SUBN subtracts Vx from Vy", opcode: 0x8127, profiles: ALL,
+        before: &[V(1, 0x10), V(2, 0x30)], after: &[V(1, 0x20), V(0xF, 1)] },
+    Case { name: "SUBN clears VF on a borrow", opcode: 0x8127, profiles: ALL,
+        before: &[V(1, 0x30), V(2, 0x10), V(0xF, 1)], after: &[V(1, 0xE0), V(0xF, 0)] },
+    Case { name: "SHL shifts Vx", opcode: 0x812E, profiles: &["default", "schip"],
+        before: &[V(1, 0x81), V(2, 0x01)], after: &[V(1, 0x02), V(0xF, 1)] },
+    Case { name: "SHL shifts Vy into Vx", opcode: 0x812E, profiles: &["vip"],
+        before: &[V(1, 0x81), V(2, 0x01), V(0xF, 1)], after: &[V(1, 0x02), V(0xF, 0)] },
+    Case { name: "SHL VF keeps the shifted out bit in VF", opcode: 0x8FFE, profiles: ALL,
+        before: &[V(0xF, 0x80)], after: &[V(0xF, 1)] },
+    Case { name: "SNE Vx, Vy skips when different", opcode: 0x9120, profiles: ALL,
+        before: &[V(1, 7), V(2, 8)], after: &[Pc(0x204)] },
+    Case { name: "SNE Vx, Vy doesn't skip when equal", opcode: 0x9120, profiles: ALL,
+        before: &[V(1, 7), V(2, 7)], after: &[] },
+    Case { name: "LD I, addr", opcode: 0xA123, profiles: ALL,
+        before: &[], after: &[I(0x123)] },
+    Case { name: "JP V0, addr", opcode: 0xB234, profiles: &["default", "vip"],
+        before: &[V(0, 4), V(2, 1)], after: &[Pc(0x238)] },
+    Case { name: "JP Vx, addr", opcode: 0xB234, profiles: &["schip"],
+        before: &[V(0, 4), V(2, 1)], after: &[Pc(0x235)] },
+    Case { name: "RND with a zero mask", opcode: 0xC300, profiles: ALL,
+        before: &[V(3, 0x55)], after: &[V(3, 0)] },
+    Case { name: "DRW draws a sprite", opcode: 0xD121, profiles: ALL,
+        before: &[I(0x300), Mem(0x300, 0xC0), V(1, 10), V(2, 5), V(0xF, 1)],
+        after: &[Pixel(10, 5, 1), Pixel(11, 5, 1), V(0xF, 0)] },
+    Case { name: "DRW sets VF on a collision", opcode: 0xD121, profiles: ALL,
+        before: &[I(0x300), Mem(0x300, 0xC0), V(1, 10), V(2, 5), Pixel(11, 5, 1)],
+        after: &[Pixel(10, 5, 1), Pixel(11, 5, 0), V(0xF, 1)] },
+    Case { name: "DRW wraps the starting position", opcode: 0xD121, profiles: ALL,
+        before: &[I(0x300), Mem(0x300, 0xC0), V(1, 74), V(2, 37)],
+        after: &[Pixel(10, 5, 1), Pixel(11, 5, 1)] },
+    Case { name: "DRW wraps sprites around the edges", opcode: 0xD122, profiles: &["default"],
+        before: &[I(0x300), Mem(0x300, 0xC0), Mem(0x301, 0xC0), V(1, 63), V(2, 31)],
+        after: &[Pixel(63, 31, 1), Pixel(0, 31, 1), Pixel(63, 0, 1), Pixel(0, 0, 1)] },
+    Case { name: "DRW clips sprites at the edges", opcode: 0xD122, profiles: &["vip", "schip"],
+        before: &[I(0x300), Mem(0x300, 0xC0), Mem(0x301, 0xC0), V(1, 63), V(2, 31)],
+        after: &[Pixel(63, 31, 1)] },
+    Case { name: "DRW with no rows draws nothing", opcode: 0xD120, profiles: ALL,
+        before: &[I(0x300), Mem(0x300, 0xC0), V(1, 10), V(2, 5), V(0xF, 1)], after: &[V(0xF, 0)] },
+    Case { name: "SKP skips when the key is pressed", opcode: 0xE59E, profiles: ALL,
+        before: &[V(5, 0xA), Key(0xA)], after: &[Pc(0x204)] },
+    Case { name: "SKP doesn't skip when the key isn't pressed", opcode: 0xE59E, profiles: ALL,
+        before: &[V(5, 0xA), Key(0xB)], after: &[] },
+    Case { name: "SKNP skips when the key isn't pressed", opcode: 0xE5A1, profiles: ALL,
+        before: &[V(5, 0xA), Key(0xB)], after: &[Pc(0x204)] },
+    Case { name: "SKNP doesn't skip when the key is pressed", opcode: 0xE5A1, profiles: ALL,
+        before: &[V(5, 0xA), Key(0xA)], after: &[] },
+    Case { name: "LD Vx, DT", opcode: 0xF407, profiles: ALL,
+        before: &[Dt(9)], after: &[V(4, 9)] },
+    Case { name: "LD Vx, K waits for a key", opcode: 0xF40A, profiles: ALL,
+        before: &[], after: &[Pc(0x200)] },
+    Case { name: "LD Vx, K ignores a key held before it started", opcode: 0xF40A, profiles: ALL,
+        before: &[Key(0xC)], after: &[Pc(0x200)] },
+    Case { name: "LD DT, Vx", opcode: 0xF415, profiles: ALL,
+        before: &[V(4, 9)], after: &[Dt(9)] },
+    Case { name: "LD ST, Vx", opcode: 0xF418, profiles: ALL,
+        before: &[V(4, 9)], after: &[St(9)] },
+    Case { name: "ADD I, Vx", opcode: 0xF41E, profiles: ALL,
+        before: &[I(0x300), V(4, 0x21)], after: &[I(0x321)] },
+    Case { name: "LD F, Vx", opcode: 0xF429, profiles: ALL,
+        before: &[V(4, 0xA)], after: &[I(50)] },
+    Case { name: "LD B, Vx stores three digits", opcode: 0xF433, profiles: ALL,
+        before: &[I(0x300), V(4, 254)], after: &[Mem(0x300, 2), Mem(0x301, 5), Mem(0x302, 4)] },
+    Case { name: "LD B, Vx pads with zeroes", opcode: 0xF433, profiles: ALL,
+        before: &[I(0x300), V(4, 7), Mem(0x300, 9), Mem(0x301, 9)],
+        after: &[Mem(0x300, 0), Mem(0x301, 0), Mem(0x302, 7)] },
+    Case { name: "LD [I], Vx leaves I alone", opcode: 0xF255, profiles: &["default", "schip"],
+        before: &[I(0x300), V(0, 1), V(1, 2), V(2, 3), V(3, 4)],
+        after: &[Mem(0x300, 1), Mem(0x301, 2), Mem(0x302, 3)] },
+    Case { name: "LD [I], Vx increments I", opcode: 0xF255, profiles: &["vip"],
+        before: &[I(0x300), V(0, 1), V(1, 2), V(2, 3), V(3, 4)],
+        after: &[Mem(0x300, 1), Mem(0x301, 2), Mem(0x302, 3), I(0x303)] },
+    Case { name: "LD Vx, [I] leaves I alone", opcode: 0xF265, profiles: &["default", "schip"],
+        before: &[I(0x300), Mem(0x300, 1), Mem(0x301, 2), Mem(0x302, 3), Mem(0x303, 4)],
+        after: &[V(0, 1), V(1, 2), V(2, 3)] },
+    Case { name: "LD Vx, [I] increments I", opcode: 0xF265, profiles: &["vip"],
+        before: &[I(0x300), Mem(0x300, 1), Mem(0x301, 2), Mem(0x302, 3), Mem(0x303, 4)],
+        after: &[V(0, 1), V(1, 2), V(2, 3), I(0x303)] },
+];
+
+fn set(cpu: &mut Cpu, field: Field) {
+    match field {
+        V(register, value) => cpu.v[register] = value,
+        I(value) => cpu.i = value,
+        Pc(value) => cpu.pc = value,
+        Sp(value) => cpu.sp = value,
+        Stack(idx, value) => cpu.stack[idx] = value,
+        Mem(address, value) => cpu.memory[address as usize] = value,
+        Pixel(x, y, value) => cpu.display[x + y * 64] = value,
+        Dt(value) => cpu.dt = value,
+        St(value) => cpu.st = value,
+        Key(key) => cpu.keys[key] = true,
+    }
+}
+
+fn setup(case: &Case, quirks: Quirks) -> Cpu {
+    let mut cpu = Cpu::new();
+    cpu.quirks = quirks;
+    cpu.rng = Some(Rng::new(1));
+    for field in case.before {
+        set(&mut cpu, *field);
+    }
+    let pc = cpu.pc as usize;
+    cpu.memory[pc..pc + 2].copy_from_slice(&case.opcode.to_be_bytes());
+    cpu
+}
+
+fn expected(case: &Case, quirks: Quirks) -> Cpu {
+    let mut cpu = setup(case, quirks);
+    cpu.pc += 2;
+    for field in case.after {
+        set(&mut cpu, *field);
+    }
+    cpu
+}
+
+// Every way the two machines differ. Stack slots above the stack pointer aren't compared,
+// they no longer hold anything.
+fn differences(expected: &Cpu, actual: &Cpu) -> Vec<String> {
+    let mut differences = vec![];
+    let mut compare = |name: String, expected: u16, actual: u16| {
+        if expected != actual {
+            differences.push(format!("{} is 0x{:X}, expected 0x{:X}", name, actual, expected));
+        }
+    };
+    compare(String::from("pc"), expected.pc, actual.pc);
+    compare(String::from("I"), expected.i, actual.i);
+    compare(String::from("sp"), expected.sp as u16, actual.sp as u16);
+    compare(String::from("DT"), expected.dt as u16, actual.dt as u16);
+    compare(String::from("ST"), expected.st as u16, actual.st as u16);
+    for register in 0..16 {
+        compare(format!("V{:X}", register), expected.v[register] as u16, actual.v[register] as u16);
+    }
+    for idx in 0..=(expected.sp as usize).min(15) {
+        compare(format!("stack[{}]", idx), expected.stack[idx], actual.stack[idx]);
+    }
+    for address in 0..expected.memory.len() {
+        compare(format!("memory[0x{:X}]", address), expected.memory[address] as u16, actual.memory[address] as u16);
+    }
+    for idx in 0..expected.display.len() {
+        let name = format!("pixel ({}, {})", idx % 64, idx / 64);
+        compare(name, expected.display[idx] as u16, actual.display[idx] as u16);
+    }
+    for key in 0..16 {
+        compare(format!("key {:X}", key), expected.keys[key] as u16, actual.keys[key] as u16);
+    }
+    differences
+}
+
+#[test]
+fn every_opcode_matches_the_reference_table() {
+    let mut failures = vec![];
+    for case in CASES {
+        for profile in case.profiles {
+            let quirks = Quirks::from_name(profile).unwrap();
+            let mut cpu = setup(case, quirks);
+            if let Err(e) = cpu.emulate_cycle() {
+                failures.push(format!("{:04X} {} [{}]: {}", case.opcode, case.name, profile, e.message));
+                continue;
+            }
+            for difference in differences(&expected(case, quirks), &cpu) {
+                failures.push(format!("{:04X} {} [{}]: {}", case.opcode, case.name, profile, difference));
+            }
+        }
+    }
+    assert!(failures.is_empty(), "\n{}", failures.join("\n"));
+}
+
+#[test]
+fn reference_table_covers_every_supported_opcode_under_every_profile() {
+    let mut covered = BTreeSet::new();
+    for case in CASES {
+        for profile in case.profiles {
+            covered.insert((disassembler::opcode_pattern(case.opcode), *profile));
+        }
+    }
+
+    for opcode in 0..=0xFFFF {
+        if !Cpu::supports_opcode(opcode) {
+            continue;
+        }
+        for (profile, _) in Quirks::PROFILES.iter() {
+            let pattern = disassembler::opcode_pattern(opcode);
+            assert!(covered.contains(&(pattern, *profile)), "no case for {} under {}", pattern, profile);
+        }
+    }
+}
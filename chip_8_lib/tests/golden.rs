@@ -0,0 +1,17 @@
+use std::path::PathBuf;
+
+use chip_8_lib::golden;
+
+#[test]
+fn bundled_roms_match_their_golden_images() {
+    let manifest_dir = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    let roms_dir = manifest_dir.join("../chip_8_wasm/static/roms");
+    let golden_dir = manifest_dir.join("tests/golden");
+
+    let mismatches = golden::check(&roms_dir, &golden_dir, false).unwrap();
+    assert!(
+        mismatches.is_empty(),
+        "{}\n\nIf the changes are intended, update the golden images with `cargo run -- golden --bless`",
+        mismatches.join("\n\n")
+    );
+}
@@ -0,0 +1,85 @@
+mod common;
+
+use chip_8_lib::cpu::Cpu;
+use chip_8_lib::rng::Rng;
+
+use common::bundled_roms;
+
+const CYCLES: usize = 20_000;
+
+// Runs a ROM, pressing a different key every so often so more of the game gets exercised.
+// Returns the machine and the result of every cycle.
+fn run(rom: &[u8], use_instruction_cache: bool) -> (Cpu, Vec<Result<(), String>>) {
+    let mut cpu = Cpu::new();
+    cpu.use_instruction_cache = use_instruction_cache;
+    cpu.rng = Some(Rng::new(1));
+    cpu.load_game(rom.to_vec());
+
+    let mut results = vec![];
+    for cycle in 0..CYCLES {
+        cpu.keys = [false; 16];
+        cpu.keys[(cycle / 500) % 16] = cycle % 1000 < 500;
+
+        let result = cpu.emulate_cycle().map_err(|e| e.message);
+        let failed = result.is_err();
+        results.push(result);
+        if failed {
+            break;
+        }
+    }
+    (cpu, results)
+}
+
+#[test]
+fn cached_and_uncached_engines_agree_on_every_bundled_rom() {
+    for (name, rom) in bundled_roms() {
+        let (cached, cached_results) = run(&rom, true);
+        let (uncached, uncached_results) = run(&rom, false);
+
+        assert_eq!(cached_results, uncached_results, "{}", name);
+        assert_eq!(&cached.memory[..], &uncached.memory[..], "{}", name);
+        assert_eq!(&cached.display[..], &uncached.display[..], "{}", name);
+        assert_eq!(
+            (cached.pc, cached.v, cached.i, cached.stack, cached.sp, cached.dt, cached.st),
+            (uncached.pc, uncached.v, uncached.i, uncached.stack, uncached.sp, uncached.dt, uncached.st),
+            "{}",
+            name
+        );
+    }
+}
+
+#[test]
+fn writes_to_code_invalidate_cached_instructions() {
+    let mut cpu = Cpu::new();
+    cpu.load_game(vec![
+        0x60, 0x12, // 0x200 LD V0 0x12
+        0x61, 0x08, // 0x202 LD V1 0x08
+        0xA2, 0x08, // 0x204 LD I 0x208
+        0xF1, 0x55, // 0x206 LD [I] V1, rewrites the next instruction to JP 0x208
+        0x00, 0xE0, // 0x208 CLS
+    ]);
+
+    // Run the CLS at 0x208 once so it is cached, then start over
+    cpu.pc = 0x208;
+    cpu.emulate_cycle().unwrap();
+    cpu.pc = 0x200;
+
+    for _ in 0..5 {
+        cpu.emulate_cycle().unwrap();
+    }
+    assert_eq!(cpu.pc, 0x208);
+}
+
+#[test]
+fn direct_writes_to_memory_run_once_the_cache_is_invalidated() {
+    let mut cpu = Cpu::new();
+    cpu.load_game(vec![0x00, 0xE0]);
+    cpu.emulate_cycle().unwrap();
+
+    // Replace the cached CLS with JP 0x200 without going through write_memory()
+    cpu.memory[0x200..0x202].copy_from_slice(&[0x12, 0x00]);
+    cpu.invalidate_instruction_cache();
+    cpu.pc = 0x200;
+    cpu.emulate_cycle().unwrap();
+    assert_eq!(cpu.pc, 0x200);
+}
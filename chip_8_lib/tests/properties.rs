@@ -0,0 +1,79 @@
+// Properties that must hold for any ROM, checked against lots of random ones. The same
+// checks run under cargo-fuzz in fuzz/fuzz_targets/emulate.rs, which finds inputs far better
+// than random bytes but needs a nightly compiler.
+use chip_8_lib::cpu::Cpu;
+use chip_8_lib::quirks::Quirks;
+use chip_8_lib::rng::Rng;
+
+const ROMS: usize = 2_000;
+const CYCLES: usize = 2_000;
+
+// Random bytes, with extra weight on opcodes that exercise the stack, memory and display
+fn random_rom(rng: &mut Rng) -> Vec<u8> {
+    let len = match rng.next_u8() % 8 {
+        0 => 0,
+        // Too big to fit in memory
+        1 => 4096 + rng.next_u8() as usize,
+        _ => rng.next_u8() as usize * 2,
+    };
+    let mut rom: Vec<u8> = (0..len).map(|_| rng.next_u8()).collect();
+    for byte in rom.iter_mut().step_by(2) {
+        if rng.next_u8() < 128 {
+            let interesting = [0x00, 0x10, 0x20, 0xB0, 0xD0, 0xE0, 0xF0];
+            *byte = (*byte & 0x0F) | interesting[rng.next_u8() as usize % interesting.len()];
+        }
+    }
+    rom
+}
+
+#[test]
+fn random_roms_never_crash_the_emulator() {
+    let mut rng = Rng::new(35);
+    for case in 0..ROMS {
+        let rom = random_rom(&mut rng);
+        let (_, quirks) = Quirks::PROFILES[case % Quirks::PROFILES.len()];
+
+        let mut cpu = Cpu::new();
+        cpu.quirks = quirks;
+        cpu.rng = Some(Rng::new(case as u64));
+        cpu.load_game(rom.clone());
+
+        for cycle in 0..CYCLES {
+            cpu.keys[rng.next_u8() as usize % 16] = rng.next_u8() < 128;
+
+            // Errors are fine, the ROM is garbage after all, but panics aren't
+            let _ = cpu.emulate_cycle();
+            assert!((cpu.pc as usize) < cpu.memory.len(), "ROM {:02X?}: pc is 0x{:X} after {} cycles", rom, cpu.pc, cycle);
+
+            if cycle % 500 == 0 {
+                let state = cpu.save_state();
+                let mut restored = Cpu::new();
+                restored.quirks = quirks;
+                restored.load_state(&state).unwrap();
+                assert_eq!(restored.save_state(), state, "ROM {:02X?}: save state changed after {} cycles", rom, cycle);
+            }
+        }
+    }
+}
+
+#[test]
+fn restored_machines_run_the_same_as_the_original() {
+    let mut rng = Rng::new(36);
+    for case in 0..ROMS / 10 {
+        let rom = random_rom(&mut rng);
+
+        let mut cpu = Cpu::new();
+        cpu.rng = Some(Rng::new(case as u64));
+        cpu.load_game(rom.clone());
+        for _ in 0..CYCLES / 2 {
+            let _ = cpu.emulate_cycle();
+        }
+
+        let mut restored = Cpu::new();
+        restored.load_state(&cpu.save_state()).unwrap();
+        for _ in 0..CYCLES / 2 {
+            assert_eq!(restored.emulate_cycle().is_ok(), cpu.emulate_cycle().is_ok());
+        }
+        assert_eq!(restored.save_state(), cpu.save_state(), "ROM {:02X?}", rom);
+    }
+}
@@ -1,11 +1,21 @@
-use chip_8_lib::cpu::Cpu;
+use chip_8_lib::cheat::{self, Cheat, CheatTiming};
+use chip_8_lib::cheat_search::{CheatSearch, SearchFilter};
+use chip_8_lib::json;
+use chip_8_lib::key_event::KeyEvent;
+use chip_8_lib::keypad::Keypad;
+use chip_8_lib::cpu::{Cpu, CYCLES_PER_FRAME};
+use chip_8_lib::crt::Crt;
+use chip_8_lib::debug_state;
 use chip_8_lib::disassembler;
-use js_sys::DataView;
+use chip_8_lib::movie::Movie;
+use chip_8_lib::palette::Palette;
+use chip_8_lib::persistence::Persistence;
+use chip_8_lib::png;
+use chip_8_lib::quirks::Quirks;
+use chip_8_lib::renderer::{self, Renderer};
 use wasm_bindgen::prelude::*;
 use web_sys::console;
 
-use wasm_bindgen::Clamped;
-use web_sys::{CanvasRenderingContext2d, ImageData};
 
 // When the `wee_alloc` feature is enabled, this uses `wee_alloc` as the global
 // allocator.
@@ -26,147 +36,540 @@ pub fn main_js() -> Result<(), JsValue> {
     Ok(())
 }
 
-static mut CPU: Cpu = Cpu {
-    memory: [0; 4096],
-    pc: 0x200, // Program counter starts at memory index 512 (0x200 in hex)
-    v: [0; 16],
-    i: 0,
-    stack: [0; 16],
-    sp: 0,
-    display: [0; 2048],
-    dt: 0,
-    st: 0,
-    keys: [false; 16],
-};
+// The wasm memory, so JavaScript can view the buffers an Emulator exposes by pointer. Views
+// have to be recreated after anything that might allocate, as growing memory detaches them.
+#[wasm_bindgen]
+pub fn memory() -> JsValue {
+    wasm_bindgen::memory()
+}
 
+// One emulated machine. The page can create as many as it likes, e.g. to run a game under
+// two quirk profiles side by side.
 #[wasm_bindgen]
-pub fn init() {
-    unsafe {
-        CPU.initialize();
+pub struct Emulator {
+    cpu: Cpu,
+
+    // The ROM that was last loaded, so a movie can restart it from the beginning
+    rom: Vec<u8>,
+
+    // The movie being recorded or played back
+    movie: Option<Movie>,
+
+    // The next frame of the movie to play, None while recording
+    playback_frame: Option<usize>,
+
+    // RGBA pixels of the display, updated by render()
+    framebuffer: Vec<u8>,
+    renderer: Renderer,
+
+    // Set when the palette or scale changes, so the next render() draws everything
+    redraw: bool,
+
+    // JavaScript copies ROMs here before calling load_rom_buffer()
+    rom_buffer: Vec<u8>,
+
+    // The RAM search in progress, and the addresses frozen every frame
+    cheat_search: CheatSearch,
+
+    // Cheats for the loaded ROM
+    cheats: Vec<Cheat>,
+
+    // The layout picked by the player, and the same layout with the loaded ROM's keys on top
+    keypad: Keypad,
+    rom_keypad: Keypad,
+
+    // Instructions stepped through with emulate_cycle() since the timers last ticked
+    steps: usize,
+}
+
+impl Default for Emulator {
+    fn default() -> Self {
+        Emulator::new()
     }
 }
 
 #[wasm_bindgen]
-pub fn update_ui() {
-    let window = web_sys::window().expect("no global `window` exists");
-    let document: web_sys::Document = window.document().expect("should have a document on window");
+impl Emulator {
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> Emulator {
+        let renderer = Renderer::default();
+        Emulator {
+            cpu: Cpu::new(),
+            rom: vec![],
+            movie: None,
+            playback_frame: None,
+            framebuffer: vec![0; renderer.width() * renderer.height() * 4],
+            renderer,
+            redraw: true,
+            rom_buffer: vec![],
+            cheat_search: CheatSearch::default(),
+            cheats: vec![],
+            keypad: Keypad::default(),
+            rom_keypad: Keypad::default(),
+            steps: 0,
+        }
+    }
+
+    // Switches to one of the quirk profiles (default, vip or schip), returns false for unknown names
+    pub fn set_quirks(&mut self, profile: &str) -> bool {
+        match Quirks::from_name(profile) {
+            Some(quirks) => {
+                self.cpu.quirks = quirks;
+                true
+            }
+            None => false,
+        }
+    }
+
+    // The registers, stack, timers, keys, breakpoints and a disassembly of `lines`
+    // instructions from the program counter on, as a plain object for any frontend to render
+    pub fn state(&self, lines: usize) -> Result<JsValue, JsValue> {
+        js_sys::JSON::parse(&debug_state::state_json(&self.cpu, lines))
+    }
+
+    // A page of memory as {page, address, bytes, written}, or null past the end of memory.
+    // written lists the addresses on the page written since clear_written().
+    pub fn memory_page(&self, page: usize) -> Result<JsValue, JsValue> {
+        match debug_state::memory_page_json(&self.cpu, page) {
+            Some(json) => js_sys::JSON::parse(&json),
+            None => Ok(JsValue::NULL),
+        }
+    }
+
+    // A convenience for the bundled page, which fills in its memorylist, registers, misc and
+    // callstack elements, skipping any that aren't on the page. Other frontends should render
+    // state() however they like instead.
+    pub fn update_ui(&self) {
+        let window = web_sys::window().expect("no global `window` exists");
+        let document: web_sys::Document = window.document().expect("should have a document on window");
+        let cpu = &self.cpu;
+
+        if let Some(memory_element) = document.get_element_by_id("memorylist") {
+            let memory_start = cpu.pc as usize;
+            let memory_end = (memory_start + 50).min(cpu.memory.len() - 1);
+
+            let mut memory_vals: Vec<String> = vec![];
+
+            for x in (memory_start..memory_end).step_by(2) {
+                let code1: u16 = cpu.memory[x] as u16;
+                let code2: u16 = cpu.memory[x + 1] as u16;
+                let opcode: u16 = code1 << 8 | code2;
+
+                memory_vals.push(format!(
+                    "<li>0x{:X} - {}</li>",
+                    x,
+                    disassembler::disassemble(opcode)
+                ));
+            }
+            let output = memory_vals.join("");
+            memory_element.set_inner_html(&output);
+        }
+
+        if let Some(registers_element) = document.get_element_by_id("registers") {
+            let mut registers: Vec<String> = vec![];
 
-    let memory_element = document.get_element_by_id("memorylist").unwrap();
-    unsafe {
-        let memory_start = CPU.pc;
-        let mut memory_end = CPU.pc + 50;
-        if memory_end >= CPU.memory.len() as u16 {
-            memory_end = CPU.memory.len() as u16;
+            for (idx, e) in cpu.v.iter().enumerate() {
+                registers.push(format!("v{}: {}", idx, e));
+            }
+            registers.push(format!("I: {}", cpu.i));
+
+            let output = registers.join("<br />");
+            registers_element.set_inner_html(&output);
         }
 
-        let mut memory_vals: Vec<String> = vec![];
+        if let Some(misc_element) = document.get_element_by_id("misc") {
+            misc_element.set_inner_html(
+                format!(
+                    "PC: {} - 0x{:X} <br />SP: {}<br />DT: {}<br /> ST: {}",
+                    cpu.pc, cpu.pc, cpu.sp, cpu.dt, cpu.st
+                )
+                .as_str(),
+            );
+        }
 
-        for x in (memory_start..memory_end).step_by(2) {
-            let code1: u16 = CPU.memory[x as usize] as u16;
-            let code2: u16 = CPU.memory[(x + 1) as usize] as u16;
-            let opcode: u16 = code1 << 8 | code2;
+        // Innermost call first, like a backtrace
+        if let Some(call_stack_element) = document.get_element_by_id("callstack") {
+            let calls: Vec<String> = debug_state::call_stack(cpu)
+                .iter()
+                .rev()
+                .map(|frame| {
+                    let subroutine = match frame.subroutine {
+                        Some(address) => format!("0x{:X}", address),
+                        None => String::from("?"),
+                    };
+                    format!(
+                        "<li>#{} {} called by {} at 0x{:X}, returns to 0x{:X}</li>",
+                        frame.depth, subroutine, frame.call, frame.call_site, frame.return_address
+                    )
+                })
+                .collect();
+            call_stack_element.set_inner_html(&calls.join(""));
+        }
+    }
 
-            memory_vals.push(format!(
-                "<li>0x{:X} - {}</li>",
-                x,
-                disassembler::disassemble(opcode)
-            ));
+    // Switches to one of the keypad layouts (qwerty, azerty or numpad), returns false for
+    // unknown names
+    pub fn set_keypad(&mut self, layout: &str) -> bool {
+        match Keypad::from_name(layout) {
+            Some(keypad) => {
+                self.use_keypad(keypad);
+                true
+            }
+            None => false,
         }
-        let output = memory_vals.join("");
-        memory_element.set_inner_html(&output);
     }
 
-    let registers_element = document.get_element_by_id("registers").unwrap();
-    unsafe {
-        let mut registers: Vec<String> = vec![];
+    // Loads a keypad config file, as saved by keypad_config()
+    pub fn load_keypad_config(&mut self, text: &str) -> Result<(), JsValue> {
+        let keypad = Keypad::parse(text).map_err(|e| JsValue::from_str(&e))?;
+        self.use_keypad(keypad);
+        Ok(())
+    }
+
+    pub fn keypad_config(&self) -> String {
+        self.keypad.to_text()
+    }
 
-        for (idx, e) in CPU.v.iter().enumerate() {
-            registers.push(format!("v{}: {}", idx.to_string(), e.to_string()));
+    // Presses the CHIP-8 key that a keyboard key (a KeyboardEvent.key) is mapped to. Returns
+    // false if it isn't mapped, so the page can let the browser handle it instead.
+    pub fn host_key_down(&mut self, host_key: &str) -> bool {
+        match self.rom_keypad.key(host_key) {
+            Some(key) => {
+                self.key_down(key);
+                true
+            }
+            None => false,
         }
-        registers.push(format!("I: {}", CPU.i));
+    }
 
-        let output = registers.join("<br />");
-        registers_element.set_inner_html(&output);
+    pub fn host_key_up(&mut self, host_key: &str) -> bool {
+        match self.rom_keypad.key(host_key) {
+            Some(key) => {
+                self.key_up(key);
+                true
+            }
+            None => false,
+        }
     }
 
-    let misc_element = document.get_element_by_id("misc").unwrap();
-    unsafe {
-        misc_element.set_inner_html(
-            format!(
-                "PC: {} - 0x{:X} <br />DT: {}<br /> ST: {}",
-                CPU.pc, CPU.pc, CPU.dt, CPU.st
-            )
-            .as_str(),
-        );
+    // Key presses and releases are queued and applied between instructions, so a key tapped
+    // faster than a frame still reaches the game
+    pub fn key_down(&mut self, key: u8) {
+        // The movie is in control of the keys during playback
+        if self.playback_frame.is_none() {
+            self.cpu.push_key_event(KeyEvent::press(key));
+        }
     }
-}
 
-#[wasm_bindgen]
-pub fn key_down(key: u8) {
-    unsafe {
-        CPU.keys[key as usize] = true;
+    pub fn key_up(&mut self, key: u8) {
+        if self.playback_frame.is_none() {
+            self.cpu.push_key_event(KeyEvent::release(key));
+        }
     }
-}
 
-#[wasm_bindgen]
-pub fn key_up(key: u8) {
-    unsafe {
-        CPU.keys[key as usize] = false;
+    // Runs a frame of CYCLES_PER_FRAME instructions and ticks the timers, the same frame movies
+    // are recorded and played back with. Returns an object with a `status` of "ok",
+    // "breakpoint", "waiting_for_key" or "error", along with the `pc` (and `register` and
+    // `pressed`, or `opcode` and `message`) where it stopped.
+    pub fn run_frame(&mut self) -> Result<JsValue, JsValue> {
+        // Movies wouldn't play back the same with memory being changed under them
+        if self.movie.is_none() {
+            self.cheat_search.apply(&mut self.cpu);
+            cheat::apply(&self.cheats, CheatTiming::EveryFrame, &mut self.cpu);
+        }
+        let result = self.cpu.run_frame();
+        self.steps = 0;
+        js_sys::JSON::parse(&result.to_json())
     }
-}
 
-#[wasm_bindgen]
-pub fn emulate_cycle() -> bool {
-    unsafe {
-        match CPU.emulate_cycle() {
-            Ok(_) => true,
+    pub fn add_breakpoint(&mut self, address: u16) {
+        self.cpu.breakpoints.insert(address);
+    }
+
+    pub fn remove_breakpoint(&mut self, address: u16) {
+        self.cpu.breakpoints.remove(&address);
+    }
+
+    // Writes bytes into memory, e.g. from a hex editor. The next instructions run and the next
+    // disassembly both see the new bytes.
+    pub fn write_memory(&mut self, address: u16, bytes: &[u8]) {
+        self.cpu.write_memory(address, bytes);
+    }
+
+    // Sets v0 to vf, i, pc, sp, dt or st, failing with a message for unknown names or values
+    // that don't fit
+    pub fn set_register(&mut self, name: &str, value: u16) -> Result<(), JsValue> {
+        self.cpu.set_register(name, value).map_err(|e| JsValue::from_str(&e))
+    }
+
+    // Forgets which bytes have been written, so memory_page() only reports newer writes
+    pub fn clear_written(&mut self) {
+        self.cpu.clear_written();
+    }
+
+    // Starts a search for the address of a value, with every address as a candidate. Returns
+    // the number of candidates.
+    pub fn start_cheat_search(&mut self) -> usize {
+        self.cheat_search.restart(&self.cpu);
+        self.cheat_search.candidates.len()
+    }
+
+    // Keeps the candidates that match `filter` since the search started or was last filtered:
+    // "unchanged", "changed", "increased", "decreased" or "equals N". Returns the number left.
+    pub fn filter_cheat_search(&mut self, filter: &str) -> Result<usize, JsValue> {
+        let filter = SearchFilter::parse(filter).map_err(|e| JsValue::from_str(&e))?;
+        Ok(self.cheat_search.filter(&self.cpu, filter))
+    }
+
+    // Up to `limit` of the addresses still being searched
+    pub fn cheat_candidates(&self, limit: usize) -> Vec<u16> {
+        self.cheat_search.candidates.iter().take(limit).cloned().collect()
+    }
+
+    // Writes `value` to `address` at the start of every frame
+    pub fn freeze(&mut self, address: u16, value: u8) {
+        self.cheat_search.freeze(address, value);
+        self.cheat_search.apply(&mut self.cpu);
+    }
+
+    pub fn unfreeze(&mut self, address: u16) {
+        self.cheat_search.unfreeze(address);
+    }
+
+    // Replaces the cheats with the ones for the loaded ROM in a cheat file, applying the `load`
+    // cheats straight away. Returns how many cheats there were for the ROM.
+    pub fn load_cheats(&mut self, text: &str) -> Result<usize, JsValue> {
+        let cheats = cheat::parse(text).map_err(|e| JsValue::from_str(&e))?;
+        self.cheats = cheat::for_rom(cheats, &self.rom);
+        cheat::apply(&self.cheats, CheatTiming::OnLoad, &mut self.cpu);
+        Ok(self.cheats.len())
+    }
+
+    // The loaded cheats as [{description, line, enabled}], where line is the cheat as written
+    // in the cheat file
+    pub fn cheats(&self) -> Result<JsValue, JsValue> {
+        let cheats = self.cheats.iter().map(|cheat| {
+            format!(
+                "{{\"description\":{},\"line\":{},\"enabled\":{}}}",
+                json::string(&cheat.description),
+                json::string(&cheat.to_line()),
+                cheat.enabled
+            )
+        });
+        js_sys::JSON::parse(&json::array(cheats))
+    }
+
+    // Turns one of the cheats() on or off, returns false if there's no such cheat
+    pub fn set_cheat_enabled(&mut self, index: usize, enabled: bool) -> bool {
+        match self.cheats.get_mut(index) {
+            Some(cheat) => {
+                cheat.enabled = enabled;
+                true
+            }
+            None => false,
+        }
+    }
+
+    // Runs a single instruction. The timers tick every CYCLES_PER_FRAME steps, so stepping
+    // through a delay loop gets to the end of it like running does.
+    pub fn emulate_cycle(&mut self) -> bool {
+        match self.cpu.emulate_cycle() {
+            Ok(_) => {
+                self.steps += 1;
+                if self.steps == CYCLES_PER_FRAME {
+                    self.cpu.tick_timers();
+                    self.steps = 0;
+                }
+                true
+            }
             Err(e) => {
                 console::error_1(&JsValue::from_str(e.message.as_str()));
                 false
             }
         }
     }
-}
 
-#[wasm_bindgen]
-pub fn draw_canvas(ctx: &CanvasRenderingContext2d) -> Result<(), JsValue> {
-    let width = 64;
-    let height = 32;
-
-    let mut data = Vec::with_capacity((width * height) as usize);
-
-    unsafe {
-        for x in CPU.display.iter() {
-            if x == &1 {
-                data.push(240); // red
-                data.push(246); // green
-                data.push(240); // blue
-                data.push(255); // alpha
-            } else {
-                data.push(34); // red
-                data.push(35); // green
-                data.push(35); // blue
-                data.push(255); // alpha
-            }
+    // Draws the part of the display that changed since the last call into the framebuffer.
+    // Returns that area as [x, y, width, height], or nothing if there's no need to redraw.
+    // The persistence filter moves on a frame every call, so call this once a frame.
+    pub fn render(&mut self) -> Vec<u32> {
+        let dirty = self.cpu.take_dirty_rect();
+        if dirty.is_none() && !self.redraw && self.renderer.persistence == Persistence::Off {
+            return vec![];
         }
+        let changed = self.renderer.update(&self.cpu.display);
+        let rect = match changed {
+            _ if self.redraw => Cpu::FULL_SCREEN,
+            Some(rect) => rect,
+            None => return vec![],
+        };
+        self.redraw = false;
+        let rect = self.renderer.render_rect(&mut self.framebuffer, rect);
+        vec![rect.x as u32, rect.y as u32, rect.width as u32, rect.height as u32]
     }
 
-    let data = ImageData::new_with_u8_clamped_array_and_sh(Clamped(&mut data), width, height)?;
-    ctx.put_image_data(&data, 0.0, 0.0)
-}
+    // Switches to a palette by name (default, green, amber or lcd) or to colours like
+    // "#000000,#FFFFFF", background first
+    pub fn set_palette(&mut self, palette: &str) -> Result<(), JsValue> {
+        self.renderer.palette = Palette::from_name_or_colors(palette).map_err(|e| JsValue::from_str(&e))?;
+        self.redraw = true;
+        Ok(())
+    }
 
-#[wasm_bindgen]
-pub fn load_game_js(data: DataView) {
-    console::log_1(&JsValue::from_str("load_game_js()"));
+    // Draws every pixel of the display as a scale x scale square. The framebuffer changes
+    // size, so the canvas has to be resized to framebuffer_width() x framebuffer_height().
+    pub fn set_scale(&mut self, scale: usize) -> Result<(), JsValue> {
+        self.renderer.scale = renderer::check_scale(scale).map_err(|e| JsValue::from_str(&e))?;
+        self.framebuffer = vec![0; self.renderer.width() * self.renderer.height() * 4];
+        self.redraw = true;
+        Ok(())
+    }
+
+    // Blends frames to hide flicker: "off", "deflicker", "phosphor" or "phosphor:DECAY"
+    pub fn set_persistence(&mut self, persistence: &str) -> Result<(), JsValue> {
+        self.renderer.persistence = Persistence::parse(persistence).map_err(|e| JsValue::from_str(&e))?;
+        self.redraw = true;
+        Ok(())
+    }
+
+    // CRT effects: "off", "crt", or a list like "scanlines=0.5,gaps,bloom,curvature=0.1".
+    // Scanlines and gaps only show at a scale of 3 or more.
+    pub fn set_crt(&mut self, crt: &str) -> Result<(), JsValue> {
+        self.renderer.crt = Crt::parse(crt).map_err(|e| JsValue::from_str(&e))?;
+        self.redraw = true;
+        Ok(())
+    }
 
-    let mut data_vec: Vec<u8> = Vec::with_capacity(data.byte_length());
+    pub fn framebuffer_width(&self) -> usize {
+        self.renderer.width()
+    }
+
+    pub fn framebuffer_height(&self) -> usize {
+        self.renderer.height()
+    }
+
+    // The display as a PNG, drawn the same way as the framebuffer
+    pub fn screenshot(&self) -> Vec<u8> {
+        png::encode(self.renderer.width(), self.renderer.height(), &self.renderer.render())
+    }
+
+    // Changes every time the display does
+    pub fn display_generation(&self) -> f64 {
+        self.cpu.display_generation as f64
+    }
+
+    pub fn framebuffer_ptr(&self) -> *const u8 {
+        self.framebuffer.as_ptr()
+    }
+
+    pub fn framebuffer_len(&self) -> usize {
+        self.framebuffer.len()
+    }
+
+    // The raw display, one byte per pixel
+    pub fn display_ptr(&self) -> *const u8 {
+        self.cpu.display.as_ptr()
+    }
+
+    pub fn display_len(&self) -> usize {
+        self.cpu.display.len()
+    }
+
+    // Makes room for a ROM of `len` bytes and returns where JavaScript should copy it to
+    pub fn rom_buffer(&mut self, len: usize) -> *mut u8 {
+        self.rom_buffer = vec![0; len];
+        self.rom_buffer.as_mut_ptr()
+    }
+
+    // Loads the ROM that was copied into the ROM buffer
+    pub fn load_rom_buffer(&mut self) {
+        self.rom = std::mem::take(&mut self.rom_buffer);
+        self.cpu.load_game(self.rom.clone());
+        // Cheats are keyed by ROM, so the old ones don't apply any more
+        self.cheats.clear();
+        self.rom_keypad = self.keypad.for_rom(&self.rom);
+    }
+
+    // Restarts the loaded ROM and records every key event from now on
+    pub fn start_recording(&mut self, seed: u32) {
+        let movie = Movie::new(&self.rom, seed as u64, self.cpu.quirks);
+        self.restart(&movie);
+        self.cpu.key_log = Some(vec![]);
+        self.movie = Some(movie);
+        self.playback_frame = None;
+    }
+
+    // Stops recording or playing back, returning the recorded movie as text
+    pub fn stop_movie(&mut self) -> Option<String> {
+        let recorded = match (self.playback_frame, self.movie.as_mut()) {
+            (None, Some(movie)) => {
+                movie.events = self.cpu.key_log.take().unwrap_or_default();
+                Some(movie.to_text())
+            }
+            _ => None,
+        };
+        self.movie = None;
+        self.playback_frame = None;
+        recorded
+    }
+
+    // Restarts the loaded ROM and plays the movie back on it
+    pub fn play_movie(&mut self, text: &str) -> Result<(), JsValue> {
+        let movie = Movie::parse(text).map_err(|e| JsValue::from_str(&e))?;
+        if !movie.matches_rom(&self.rom) {
+            return Err(JsValue::from_str("movie was recorded with a different ROM"));
+        }
+        self.restart(&movie);
+        if !movie.has_events() {
+            self.cpu.keys = movie.keys_for_frame(0).unwrap_or([false; 16]);
+        }
+        self.movie = Some(movie);
+        self.playback_frame = Some(0);
+        Ok(())
+    }
+
+    // Called after each frame's worth of cycles. Returns false once a movie has finished playing.
+    pub fn end_frame(&mut self) -> bool {
+        let movie = match self.movie.as_mut() {
+            Some(movie) => movie,
+            None => return true,
+        };
+        match self.playback_frame {
+            None => movie.record_frame(&self.cpu.keys),
+            Some(frame) => match movie.keys_for_frame(frame + 1) {
+                Some(keys) => {
+                    if !movie.has_events() {
+                        self.cpu.keys = keys;
+                    }
+                    self.playback_frame = Some(frame + 1);
+                }
+                None => {
+                    self.cpu.keys = [false; 16];
+                    self.movie = None;
+                    self.playback_frame = None;
+                    return false;
+                }
+            },
+        }
+        true
+    }
+}
 
-    // TODO Is there a better way to get the DataView data into the vec?
-    for idx in 0..data.byte_length() {
-        data_vec.push(data.get_uint8(idx));
+impl Emulator {
+    fn use_keypad(&mut self, keypad: Keypad) {
+        self.rom_keypad = keypad.for_rom(&self.rom);
+        self.keypad = keypad;
     }
 
-    unsafe {
-        CPU.load_game(data_vec);
+    // Breakpoints belong to the debugger rather than the machine, so they're kept
+    fn restart(&mut self, movie: &Movie) {
+        let quirks = self.cpu.quirks;
+        let breakpoints = std::mem::take(&mut self.cpu.breakpoints);
+        self.cpu = Cpu::new();
+        self.cpu.quirks = quirks;
+        self.cpu.breakpoints = breakpoints;
+        self.steps = 0;
+        self.cpu.load_game(self.rom.clone());
+        movie.start(&mut self.cpu);
     }
 }
@@ -1,27 +1,37 @@
 const canvas = document.getElementById('chip8-canvas');
 const ctx = canvas.getContext('2d');
+const statusElement = document.getElementById('status');
 
 
 let isRunning = false;
+let isRecording = false;
+let wasmMemory;
+
+// The page of memory shown in the hex view
+let hexPage = 2;
 
 import("./crate/pkg/index.js").then(wasm => {
 
-  wasm.init();
+  wasmMemory = wasm.memory();
+  const emulator = new wasm.Emulator();
 
-  loadRom(wasm, 'PONG2')
-    // loadRom(wasm, 'WIPEOFF')
+  loadRom(emulator, 'PONG2')
+    // loadRom(emulator, 'WIPEOFF')
     .then(() => {
       console.log('ROM finished loading');
-      runLoop(wasm);
+      runLoop(emulator);
 
-      wasm.draw_canvas(ctx);
-      wasm.update_ui();
+      drawCanvas(emulator);
+      updateDebugger(emulator);
     });
 
 
   const startButton = document.getElementById("start-button");
   const stopButton = document.getElementById("stop-button");
   const stepButton = document.getElementById("step-button");
+  const recordButton = document.getElementById("record-button");
+  const movieInput = document.getElementById("movie-input");
+  const cheatInput = document.getElementById("cheat-input");
 
   startButton.addEventListener('click', () => {
     isRunning = true;
@@ -31,96 +41,340 @@ import("./crate/pkg/index.js").then(wasm => {
   });
 
   stopButton.addEventListener('click', () => {
-    isRunning = false;
-    startButton.disabled = false;
-    stopButton.disabled = true;
-    stepButton.disabled = false;
+    stop();
   });
 
   stepButton.addEventListener('click', () => {
-    doStep(wasm);
+    emulator.clear_written();
+    doStep(emulator);
+    drawCanvas(emulator);
+    updateDebugger(emulator);
+  });
+
+  document.getElementById("hex-previous").addEventListener('click', () => {
+    hexPage = Math.max(hexPage - 1, 0);
+    updateDebugger(emulator);
+  });
+
+  document.getElementById("hex-next").addEventListener('click', () => {
+    if (emulator.memory_page(hexPage + 1) !== null) {
+      hexPage += 1;
+    }
+    updateDebugger(emulator);
+  });
+
+  // Addresses and values are hexadecimal, bytes are separated by spaces
+  document.getElementById("poke-form").addEventListener('submit', event => {
+    event.preventDefault();
+    const address = parseInt(document.getElementById("poke-address").value, 16);
+    const bytes = document.getElementById("poke-bytes").value.trim().split(/\s+/).map(byte => parseInt(byte, 16));
+    if (isNaN(address) || bytes.some(byte => isNaN(byte) || byte > 0xff)) {
+      statusElement.textContent = 'Enter an address and bytes in hexadecimal';
+      return;
+    }
+    emulator.write_memory(address, new Uint8Array(bytes));
+    updateDebugger(emulator);
+  });
+
+  document.getElementById("register-form").addEventListener('submit', event => {
+    event.preventDefault();
+    const name = document.getElementById("register-name").value.trim();
+    const value = parseInt(document.getElementById("register-value").value, 16);
+    try {
+      emulator.set_register(name, value);
+    } catch (error) {
+      statusElement.textContent = error;
+    }
+    updateDebugger(emulator);
+  });
+
+  recordButton.addEventListener('click', () => {
+    if (isRecording) {
+      const movie = emulator.stop_movie();
+      if (movie !== undefined) {
+        downloadMovie(movie);
+      }
+      isRecording = false;
+      recordButton.textContent = 'Record';
+    } else {
+      emulator.start_recording(Math.floor(Math.random() * 0xffffffff));
+      isRecording = true;
+      recordButton.textContent = 'Save recording';
+    }
+  });
+
+  movieInput.addEventListener('change', () => {
+    const file = movieInput.files[0];
+    if (file === undefined) {
+      return;
+    }
+    file.text().then(text => {
+      emulator.play_movie(text);
+      isRecording = false;
+      recordButton.textContent = 'Record';
+    }).catch(console.error);
+  });
+
+  cheatInput.addEventListener('change', () => {
+    const file = cheatInput.files[0];
+    if (file === undefined) {
+      return;
+    }
+    file.text().then(text => {
+      try {
+        const count = emulator.load_cheats(text);
+        statusElement.textContent = `Loaded ${count} cheats`;
+      } catch (error) {
+        statusElement.textContent = error;
+      }
+      drawCheats(emulator);
+      updateDebugger(emulator);
+    }).catch(console.error);
+  });
+
+  const keypadSelect = document.getElementById("keypad-select");
+  keypadSelect.value = localStorage.getItem('keypad') || 'qwerty';
+  emulator.set_keypad(keypadSelect.value);
+  keypadSelect.addEventListener('change', () => {
+    emulator.set_keypad(keypadSelect.value);
+    localStorage.setItem('keypad', keypadSelect.value);
+  });
+
+  const paletteSelect = document.getElementById("palette-select");
+  const paletteColors = document.getElementById("palette-colors");
+  const setPalette = () => {
+    paletteColors.hidden = paletteSelect.value !== 'custom';
+    try {
+      emulator.set_palette(paletteSelect.value === 'custom' ? paletteColors.value : paletteSelect.value);
+      localStorage.setItem('palette', paletteSelect.value);
+      localStorage.setItem('palette-colors', paletteColors.value);
+    } catch (error) {
+      statusElement.textContent = error;
+    }
+    drawCanvas(emulator);
+  };
+  paletteSelect.value = localStorage.getItem('palette') || 'default';
+  paletteColors.value = localStorage.getItem('palette-colors') || '';
+  paletteSelect.addEventListener('change', setPalette);
+  paletteColors.addEventListener('change', setPalette);
+  setPalette();
+
+  // Higher scales are drawn by the emulator rather than stretched by the browser, so the
+  // canvas always ends up 512 pixels wide
+  const scaleSelect = document.getElementById("scale-select");
+  scaleSelect.value = localStorage.getItem('scale') || '1';
+  const setScale = () => {
+    try {
+      emulator.set_scale(Number(scaleSelect.value));
+      localStorage.setItem('scale', scaleSelect.value);
+    } catch (error) {
+      statusElement.textContent = error;
+    }
+    canvas.width = emulator.framebuffer_width();
+    canvas.height = emulator.framebuffer_height();
+    canvas.style.transform = `scale(${512 / canvas.width})`;
+    drawCanvas(emulator);
+  };
+  scaleSelect.addEventListener('change', setScale);
+  setScale();
+
+  const persistenceSelect = document.getElementById("persistence-select");
+  persistenceSelect.value = localStorage.getItem('persistence') || 'off';
+  emulator.set_persistence(persistenceSelect.value);
+  persistenceSelect.addEventListener('change', () => {
+    emulator.set_persistence(persistenceSelect.value);
+    localStorage.setItem('persistence', persistenceSelect.value);
+    drawCanvas(emulator);
+  });
+
+  const crtSelect = document.getElementById("crt-select");
+  const crtEffects = document.getElementById("crt-effects");
+  const setCrt = () => {
+    crtEffects.hidden = crtSelect.value !== 'custom';
+    try {
+      emulator.set_crt(crtSelect.value === 'custom' ? crtEffects.value : crtSelect.value);
+      localStorage.setItem('crt', crtSelect.value);
+      localStorage.setItem('crt-effects', crtEffects.value);
+    } catch (error) {
+      statusElement.textContent = error;
+    }
+    drawCanvas(emulator);
+  };
+  crtSelect.value = localStorage.getItem('crt') || 'off';
+  crtEffects.value = localStorage.getItem('crt-effects') || '';
+  crtSelect.addEventListener('change', setCrt);
+  crtEffects.addEventListener('change', setCrt);
+  setCrt();
+
+  document.getElementById("screenshot-button").addEventListener('click', () => {
+    download(new Blob([emulator.screenshot()], { type: 'image/png' }), 'screenshot.png');
   });
 
+  // The emulator maps keys to the keypad using the chosen layout and the ROM's own keys
   document.addEventListener("keydown", event => {
-    let keyCode = keyMap[event.key];
-    if (keyCode >= 0 && keyCode <= 0xf) {
-      wasm.key_down(keyMap[event.key]);
+    if (isTyping(event)) {
+      return;
+    }
+    if (emulator.host_key_down(event.key)) {
+      event.preventDefault();
     }
   });
 
   document.addEventListener("keyup", event => {
-    let keyCode = keyMap[event.key];
-    if (keyCode >= 0 && keyCode <= 0xf) {
-      wasm.key_up(keyMap[event.key]);
+    if (isTyping(event)) {
+      return;
+    }
+    if (emulator.host_key_up(event.key)) {
+      event.preventDefault();
     }
   });
 
 }).catch(console.error);
 
-function doStep(wasm) {
-  let result = wasm.emulate_cycle();
-  if (result === true) {
-    wasm.draw_canvas(ctx);
-    wasm.update_ui();
-  } else {
+function doStep(emulator) {
+  let result = emulator.emulate_cycle();
+  if (result !== true) {
     isRunning = false;
   }
 }
 
-function runLoop(wasm) {
+function stop() {
+  isRunning = false;
+  document.getElementById("start-button").disabled = false;
+  document.getElementById("stop-button").disabled = true;
+  document.getElementById("step-button").disabled = false;
+}
+
+function runLoop(emulator) {
   if (isRunning) {
-    // Run 9 steps to emulate a ~540hz cpu
-    for (let x = 0; x <= 9; x++) {
-      doStep(wasm);
-      if (!isRunning) {
-        return;
-      }
+    // The hex view highlights what the last frame wrote
+    emulator.clear_written();
+    const result = emulator.run_frame();
+    statusElement.textContent = '';
+    switch (result.status) {
+      case 'waiting_for_key':
+        statusElement.textContent = result.pressed === null
+          ? 'Waiting for a key'
+          : `Waiting for key ${result.pressed.toString(16)} to be released`;
+        // Falls through, the frame still counts
+      case 'ok':
+        // Movies are recorded and played back a frame at a time
+        if (!emulator.end_frame()) {
+          console.log('Movie finished playing');
+        }
+        break;
+      case 'breakpoint':
+        stop();
+        statusElement.textContent = `Breakpoint at 0x${result.pc.toString(16)}`;
+        break;
+      case 'error':
+        stop();
+        statusElement.textContent = `${result.message} at 0x${result.pc.toString(16)}`;
+        console.error(statusElement.textContent);
+        break;
     }
+
+    // Only redraw once per frame, rather than after every instruction
+    drawCanvas(emulator);
+    updateDebugger(emulator);
   }
 
   window.requestAnimationFrame(() => {
-    runLoop(wasm);
+    runLoop(emulator);
   });
 }
 
 
-async function loadRom(wasm, name) {
-  let i = await fetch(`roms/${name}`);
-  let buffer = await i.arrayBuffer();
-  const rom = new DataView(buffer, 0, buffer.byteLength);
-  wasm.load_game_js(rom);
+function updateDebugger(emulator) {
+  emulator.update_ui();
+  drawHexView(emulator);
 }
 
+// Hex and ASCII view of one page of memory, highlighting the bytes PC and I point at and the
+// bytes written since clear_written()
+function drawHexView(emulator) {
+  const page = emulator.memory_page(hexPage);
+  const state = emulator.state(0);
+  const written = new Set(page.written);
+  const rows = [];
+  for (let row = 0; row < page.bytes.length; row += 16) {
+    let hex = '';
+    let ascii = '';
+    for (let offset = row; offset < row + 16; offset++) {
+      const address = page.address + offset;
+      const byte = page.bytes[offset];
+      let className = '';
+      if (address === state.pc || address === state.pc + 1) {
+        className = 'pc';
+      } else if (address === state.i) {
+        className = 'i';
+      } else if (written.has(address)) {
+        className = 'written';
+      }
+      hex += ` <span class="${className}">${byte.toString(16).padStart(2, '0')}</span>`;
+      ascii += byte >= 0x20 && byte < 0x7f ? escapeHtml(String.fromCharCode(byte)) : '.';
+    }
+    rows.push(`${(page.address + row).toString(16).padStart(3, '0')}:${hex}  ${ascii}`);
+  }
+  document.getElementById("hexdump").innerHTML = rows.join('\n');
+  document.getElementById("hex-page").textContent = `Page ${hexPage}`;
+}
 
-// CHIP-8 Keypad    User Keyboard
-// +-+-+-+-+        +-+-+-+-+
-// |1|2|3|C|        |1|2|3|4|
-// +-+-+-+-+        +-+-+-+-+
-// |4|5|6|D|        |Q|W|E|R|
-// +-+-+-+-+   <=   +-+-+-+-+
-// |7|8|9|E|        |A|S|D|F|
-// +-+-+-+-+        +-+-+-+-+
-// |A|0|B|F|        |Z|X|C|V|
-// +-+-+-+-+        +-+-+-+-+
-
-const keyMap = {
-  '1': 0x1,
-  '2': 0x2,
-  '3': 0x3,
-  '4': 0xc,
-
-  'q': 0x4,
-  'w': 0x5,
-  'e': 0x6,
-  'r': 0xd,
-
-  'a': 0x7,
-  's': 0x8,
-  'd': 0x9,
-  'f': 0xe,
-
-  'z': 0xa,
-  'x': 0x0,
-  'c': 0xb,
-  'v': 0xf,
-};
+// A checkbox for each cheat to turn it on and off
+function drawCheats(emulator) {
+  const list = document.getElementById("cheatlist");
+  list.innerHTML = '';
+  emulator.cheats().forEach((cheat, index) => {
+    const checkbox = document.createElement('input');
+    checkbox.type = 'checkbox';
+    checkbox.checked = cheat.enabled;
+    checkbox.addEventListener('change', () => {
+      emulator.set_cheat_enabled(index, checkbox.checked);
+    });
+    const item = document.createElement('li');
+    item.title = cheat.line;
+    item.append(checkbox, ` ${cheat.description || cheat.line}`);
+    list.appendChild(item);
+  });
+}
+
+// Keys typed into the debugger's inputs shouldn't press keypad keys
+function isTyping(event) {
+  return event.target instanceof HTMLInputElement || event.target instanceof HTMLSelectElement;
+}
+
+function escapeHtml(text) {
+  return text.replace(/&/g, '&amp;').replace(/</g, '&lt;').replace(/>/g, '&gt;');
+}
+
+// Blits the part of the emulator's framebuffer that changed straight out of wasm memory
+function drawCanvas(emulator) {
+  const dirty = emulator.render();
+  if (dirty.length === 0) {
+    return;
+  }
+  const pixels = new Uint8ClampedArray(wasmMemory.buffer, emulator.framebuffer_ptr(), emulator.framebuffer_len());
+  const image = new ImageData(pixels, canvas.width, canvas.height);
+  ctx.putImageData(image, 0, 0, dirty[0], dirty[1], dirty[2], dirty[3]);
+}
+
+function downloadMovie(text) {
+  download(new Blob([text], { type: 'text/plain' }), 'recording.c8m');
+}
+
+function download(blob, fileName) {
+  const link = document.createElement('a');
+  link.href = URL.createObjectURL(blob);
+  link.download = fileName;
+  link.click();
+  URL.revokeObjectURL(link.href);
+}
+
+async function loadRom(emulator, name) {
+  let i = await fetch(`roms/${name}`);
+  let buffer = await i.arrayBuffer();
+  const rom = new Uint8Array(buffer);
+  const ptr = emulator.rom_buffer(rom.length);
+  new Uint8Array(wasmMemory.buffer, ptr, rom.length).set(rom);
+  emulator.load_rom_buffer();
+}